@@ -0,0 +1,245 @@
+use crate::Complex;
+use egui::Color32;
+use std::f32::consts::{PI, TAU};
+pub fn hsv2rgb(hue: f32, sat: f32, val: f32) -> [u8; 3] {
+    if sat == 0.0 {
+        return rgb2val(val, val, val);
+    }
+    let i = hue.floor();
+    let f = hue - i;
+    let p = val * (1.0 - sat);
+    let q = val * (1.0 - sat * f);
+    let t = val * (1.0 - sat * (1.0 - f));
+    match i as usize % 6 {
+        0 => rgb2val(val, t, p),
+        1 => rgb2val(q, val, p),
+        2 => rgb2val(p, val, t),
+        3 => rgb2val(p, q, val),
+        4 => rgb2val(t, p, val),
+        _ => rgb2val(val, p, q),
+    }
+}
+fn rgb2val(r: f32, g: f32, b: f32) -> [u8; 3] {
+    [(255.0 * r) as u8, (255.0 * g) as u8, (255.0 * b) as u8]
+}
+pub fn srgb_to_linear(c: f32) -> f32 {
+    if c <= 0.04045 {
+        c / 12.92
+    } else {
+        ((c + 0.055) / 1.055).powf(2.4)
+    }
+}
+pub fn linear_to_srgb(c: f32) -> f32 {
+    if c <= 0.0031308 {
+        c * 12.92
+    } else {
+        1.055 * c.powf(1.0 / 2.4) - 0.055
+    }
+}
+pub fn get_lch(color: [f32; 3]) -> (f32, f32, f32) {
+    let c = (color[1].powi(2) + color[2].powi(2)).sqrt();
+    let h = color[2].atan2(color[1]);
+    (color[0], c, h)
+}
+pub fn rgb_to_oklch(color: &mut [f32; 3]) {
+    let mut l = 0.412_221_46 * color[0] + 0.536_332_55 * color[1] + 0.051_445_995 * color[2];
+    let mut m = 0.211_903_5 * color[0] + 0.680_699_5 * color[1] + 0.107_396_95 * color[2];
+    let mut s = 0.088_302_456 * color[0] + 0.281_718_85 * color[1] + 0.629_978_7 * color[2];
+
+    l = l.cbrt();
+    m = m.cbrt();
+    s = s.cbrt();
+
+    color[0] = 0.210_454_27 * l + 0.793_617_8 * m - 0.004_072_043 * s;
+    color[1] = 1.977_998_5 * l - 2.428_592_2 * m + 0.450_593_7 * s;
+    color[2] = 0.025_904_043 * l + 0.782_771_7 * m - 0.808_675_77 * s;
+}
+pub fn oklch_to_rgb(color: &mut [f32; 3]) {
+    let mut l = color[0] + 0.396_337_78 * color[1] + 0.215_803_76 * color[2];
+    let mut m = color[0] - 0.105_561_346 * color[1] - 0.063_854_17 * color[2];
+    let mut s = color[0] - 0.089_484_18 * color[1] - 1.291_485_5 * color[2];
+
+    l = l.powi(3);
+    m = m.powi(3);
+    s = s.powi(3);
+
+    color[0] = 4.076_741_7 * l - 3.307_711_6 * m + 0.230_969_9 * s;
+    color[1] = -1.268_438 * l + 2.609_757_4 * m - 0.341_319_38 * s;
+    color[2] = -0.004_196_076 * l - 0.703_418_6 * m + 1.707_614_7 * s;
+}
+pub fn color_to_oklch(color: Color32) -> (f32, f32, f32) {
+    let mut color = [color.r(), color.g(), color.b()].map(|c| srgb_to_linear(c as f32 / 255.0));
+    rgb_to_oklch(&mut color);
+    get_lch(color)
+}
+fn oklch_to_linear(l: f32, c: f32, h: f32) -> [f32; 3] {
+    let mut color = [l, c * h.cos(), c * h.sin()];
+    oklch_to_rgb(&mut color);
+    color
+}
+fn in_gamut(color: [f32; 3]) -> bool {
+    color.iter().all(|c| (-1e-4..=1.0 + 1e-4).contains(c))
+}
+pub fn oklch_to_color(l: f32, c: f32, h: f32) -> Color32 {
+    let l = l.clamp(0.0, 1.0);
+    let mut color = oklch_to_linear(l, c, h);
+    if !in_gamut(color) {
+        let (mut lo, mut hi) = (0.0, c);
+        for _ in 0..16 {
+            let mid = (lo + hi) / 2.0;
+            if in_gamut(oklch_to_linear(l, mid, h)) {
+                lo = mid
+            } else {
+                hi = mid
+            }
+        }
+        color = oklch_to_linear(l, lo, h);
+    }
+    let [r, g, b] = color.map(|c| (linear_to_srgb(c.clamp(0.0, 1.0)) * 255.0).round() as u8);
+    Color32::from_rgb(r, g, b)
+}
+pub fn palette(n: usize, lightness: f32, chroma: f32) -> Vec<Color32> {
+    (0..n)
+        .map(|i| oklch_to_color(lightness, chroma, 0.5 + TAU * i as f32 / n as f32))
+        .collect()
+}
+pub fn alt_palette(colors: &[Color32], lightness: f32) -> Vec<Color32> {
+    colors
+        .iter()
+        .map(|c| {
+            let (l, c, h) = color_to_oklch(*c);
+            oklch_to_color(l + lightness, c, h)
+        })
+        .collect()
+}
+pub fn color_blind_palette() -> Vec<Color32> {
+    vec![
+        Color32::from_rgb(230, 159, 0),
+        Color32::from_rgb(86, 180, 233),
+        Color32::from_rgb(0, 158, 115),
+        Color32::from_rgb(240, 228, 66),
+        Color32::from_rgb(0, 114, 178),
+        Color32::from_rgb(213, 94, 0),
+        Color32::from_rgb(204, 121, 167),
+    ]
+}
+pub fn shift_hue(diff: f32, color: &Color32) -> Color32 {
+    let (l, c, h) = color_to_oklch(*color);
+    oklch_to_color(l, c, (h + TAU * diff).rem_euclid(TAU))
+}
+pub trait ColorScheme: Send + Sync {
+    fn color(&self, z: Complex) -> [u8; 3];
+}
+impl<F: Fn(Complex) -> [u8; 3] + Send + Sync> ColorScheme for F {
+    fn color(&self, z: Complex) -> [u8; 3] {
+        self(z)
+    }
+}
+fn parts(z: Complex) -> (f32, f32) {
+    let (x, y) = z.to_options();
+    (x.unwrap_or(0.0), y.unwrap_or(0.0))
+}
+fn phase_hue(x: f32, y: f32) -> f32 {
+    3.0 * (1.0 - y.atan2(x) / PI)
+}
+pub struct Classic;
+impl ColorScheme for Classic {
+    fn color(&self, z: Complex) -> [u8; 3] {
+        let (x, y) = parts(z);
+        let abs = x.hypot(y);
+        let hue = phase_hue(x, y);
+        let sat = (1.0 + abs.fract()) / 2.0;
+        let val = {
+            let t1 = (x * PI).sin();
+            let t2 = (y * PI).sin();
+            (t1 * t2).abs().powf(0.125)
+        };
+        hsv2rgb(hue, sat, val)
+    }
+}
+pub struct Phase;
+impl ColorScheme for Phase {
+    fn color(&self, z: Complex) -> [u8; 3] {
+        let (x, y) = parts(z);
+        hsv2rgb(phase_hue(x, y), 1.0, 1.0)
+    }
+}
+pub struct PhaseModulus;
+impl ColorScheme for PhaseModulus {
+    fn color(&self, z: Complex) -> [u8; 3] {
+        let (x, y) = parts(z);
+        let m = x.hypot(y).log2().rem_euclid(1.0);
+        hsv2rgb(phase_hue(x, y), 1.0, 0.6 + 0.4 * m)
+    }
+}
+pub struct EnhancedPhase;
+impl ColorScheme for EnhancedPhase {
+    fn color(&self, z: Complex) -> [u8; 3] {
+        let (x, y) = parts(z);
+        let m = x.hypot(y).log2().rem_euclid(1.0);
+        let p = (12.0 * y.atan2(x) / TAU).rem_euclid(1.0);
+        hsv2rgb(phase_hue(x, y), 1.0, (0.6 + 0.4 * m) * (0.6 + 0.4 * p))
+    }
+}
+pub struct OklchPhase;
+impl ColorScheme for OklchPhase {
+    fn color(&self, z: Complex) -> [u8; 3] {
+        let (x, y) = parts(z);
+        let [r, g, b, _] = oklch_to_color(0.7, 0.12, y.atan2(x)).to_array();
+        [r, g, b]
+    }
+}
+pub struct RiemannSphere;
+impl ColorScheme for RiemannSphere {
+    fn color(&self, z: Complex) -> [u8; 3] {
+        let (x, y) = parts(z);
+        let abs = x.hypot(y);
+        if abs.is_infinite() {
+            return [255, 255, 255];
+        }
+        let l = abs.atan() * 2.0 / PI;
+        let c = 0.15 * (1.0 - (2.0 * l - 1.0).powi(2));
+        let [r, g, b, _] = oklch_to_color(l, c, y.atan2(x)).to_array();
+        [r, g, b]
+    }
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    fn hue_diff(a: f32, b: f32) -> f32 {
+        let d = (a - b).rem_euclid(TAU);
+        d.min(TAU - d)
+    }
+    #[test]
+    fn oklch_round_trip() {
+        for r in (0..=255).step_by(15) {
+            for g in (0..=255).step_by(15) {
+                for b in (0..=255).step_by(15) {
+                    let color = Color32::from_rgb(r, g, b);
+                    let (l, c, h) = color_to_oklch(color);
+                    let back = oklch_to_color(l, c, h);
+                    for (x, y) in color.to_array().iter().zip(back.to_array()) {
+                        assert!(x.abs_diff(y) <= 1, "{color:?} -> {back:?}");
+                    }
+                }
+            }
+        }
+    }
+    #[test]
+    fn gamut_mapping() {
+        for i in 0..12 {
+            let h = TAU * i as f32 / 12.0;
+            for l in [0.3, 0.5, 0.7, 0.9] {
+                let raw = oklch_to_linear(l, 0.5, h);
+                assert!(!in_gamut(raw));
+                let color = oklch_to_color(l, 0.5, h);
+                let (ml, mc, mh) = color_to_oklch(color);
+                assert!(mc < 0.5);
+                assert!((ml - l).abs() < 0.01, "{l} {ml}");
+                assert!(hue_diff(mh, h) < 0.05, "{h} {mh}");
+            }
+        }
+        assert_eq!(oklch_to_color(1.5, 0.3, 1.0), Color32::WHITE);
+        assert_eq!(oklch_to_color(-0.5, 0.3, 1.0), Color32::BLACK);
+    }
+}
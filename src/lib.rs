@@ -1,9 +1,18 @@
 use egui::{
-    Align2, CentralPanel, Color32, ColorImage, Context, FontId, Key, Painter, Pos2, Rangef, Rect,
-    Stroke, TextureHandle, TextureOptions, Ui, Vec2,
+    Align2, CentralPanel, Color32, ColorImage, Context, Event, FontFamily, FontId, Galley, Key,
+    Painter, Pos2, Rangef, Rect, Shape, Stroke, TextureHandle, TextureOptions, Ui, Vec2,
+    emath::Rot2, epaint::TextShape,
 };
 use std::f32::consts::{PI, TAU};
+pub mod color;
+use color::shift_hue;
+pub use color::{ColorScheme, alt_palette, color_blind_palette, get_lch, palette, rgb_to_oklch};
+use std::collections::HashMap;
+use std::collections::hash_map::Entry;
 use std::ops::{Add, AddAssign, Mul, Sub, SubAssign};
+use std::sync::Arc;
+#[derive(Copy, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum GraphMode {
     Normal,
     Slice,
@@ -13,17 +22,174 @@ pub enum GraphMode {
     Flatten,
     Depth,
 }
+#[derive(Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum GraphType {
     Width(Vec<Complex>, f32, f32),
     Coord(Vec<(f32, Complex)>),
     Width3D(Vec<Complex>, f32, f32, f32, f32),
     Coord3D(Vec<(f32, f32, Complex)>),
+    #[cfg_attr(feature = "serde", serde(skip))]
+    Function(Arc<dyn Fn(f32) -> Complex + Send + Sync>),
+    #[cfg_attr(feature = "serde", serde(skip))]
+    Function3D(Arc<dyn Fn(f32, f32) -> Complex + Send + Sync>),
+}
+#[derive(Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Annotation {
+    Text(Vec3, String),
+    Arrow(Vec3, Vec3),
+    //vline, hline and span mark the 2d axes and are not drawn in 3d
+    VLine(f32),
+    HLine(f32),
+    Span(f32, f32),
+    Circle(Vec3, f32),
+}
+#[derive(Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Theme {
+    pub background_color: Color32,
+    pub axis_color: Color32,
+    pub axis_color_light: Color32,
+    pub text_color: Color32,
+    pub main_colors: Vec<Color32>,
+    pub alt_colors: Vec<Color32>,
+}
+impl Theme {
+    pub fn light() -> Self {
+        Self {
+            background_color: Color32::WHITE,
+            axis_color: Color32::BLACK,
+            axis_color_light: Color32::LIGHT_GRAY,
+            text_color: Color32::BLACK,
+            main_colors: vec![
+                Color32::from_rgb(255, 85, 85),
+                Color32::from_rgb(85, 85, 255),
+                Color32::from_rgb(255, 85, 255),
+                Color32::from_rgb(85, 255, 85),
+                Color32::from_rgb(85, 255, 255),
+                Color32::from_rgb(255, 255, 85),
+            ],
+            alt_colors: vec![
+                Color32::from_rgb(170, 0, 0),
+                Color32::from_rgb(0, 0, 170),
+                Color32::from_rgb(170, 0, 170),
+                Color32::from_rgb(0, 170, 0),
+                Color32::from_rgb(0, 170, 170),
+                Color32::from_rgb(170, 170, 0),
+            ],
+        }
+    }
+    pub fn dark() -> Self {
+        Self {
+            background_color: Color32::from_gray(27),
+            axis_color: Color32::from_gray(230),
+            axis_color_light: Color32::from_gray(70),
+            text_color: Color32::from_gray(230),
+            main_colors: vec![
+                Color32::from_rgb(240, 100, 100),
+                Color32::from_rgb(110, 140, 255),
+                Color32::from_rgb(220, 110, 230),
+                Color32::from_rgb(100, 210, 110),
+                Color32::from_rgb(80, 200, 210),
+                Color32::from_rgb(230, 200, 80),
+            ],
+            alt_colors: vec![
+                Color32::from_rgb(255, 170, 170),
+                Color32::from_rgb(170, 190, 255),
+                Color32::from_rgb(240, 180, 245),
+                Color32::from_rgb(170, 240, 175),
+                Color32::from_rgb(160, 235, 240),
+                Color32::from_rgb(250, 230, 160),
+            ],
+        }
+    }
+    pub fn high_contrast() -> Self {
+        Self {
+            background_color: Color32::BLACK,
+            axis_color: Color32::WHITE,
+            axis_color_light: Color32::GRAY,
+            text_color: Color32::WHITE,
+            main_colors: vec![
+                Color32::from_rgb(255, 255, 0),
+                Color32::from_rgb(0, 255, 255),
+                Color32::from_rgb(255, 0, 255),
+                Color32::from_rgb(0, 255, 0),
+                Color32::from_rgb(255, 128, 0),
+                Color32::from_rgb(255, 255, 255),
+            ],
+            alt_colors: vec![
+                Color32::from_rgb(255, 255, 170),
+                Color32::from_rgb(170, 255, 255),
+                Color32::from_rgb(255, 170, 255),
+                Color32::from_rgb(170, 255, 170),
+                Color32::from_rgb(255, 200, 140),
+                Color32::from_rgb(190, 190, 190),
+            ],
+        }
+    }
+    pub fn color_blind() -> Self {
+        let main_colors = color_blind_palette();
+        Self {
+            alt_colors: alt_palette(&main_colors, -0.2),
+            main_colors,
+            ..Self::light()
+        }
+    }
+}
+#[derive(Copy, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Discontinuity {
+    pub range: f32,
+    pub neighbours: f32,
+    pub sign_flip: bool,
+}
+impl Default for Discontinuity {
+    fn default() -> Self {
+        Self {
+            range: 0.25,
+            neighbours: 8.0,
+            sign_flip: true,
+        }
+    }
+}
+#[derive(Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct FontConfig {
+    pub family: FontFamily,
+    pub tick_size: f32,
+    pub label_size: f32,
+    pub title_size: f32,
+    pub readout_size: f32,
+    //sizes are in points so egui already scales them for hidpi screens, this only
+    //enlarges text for images rendered at a different scale
+    pub export_scale: f32,
+}
+impl Default for FontConfig {
+    fn default() -> Self {
+        Self {
+            family: FontFamily::Monospace,
+            tick_size: 16.0,
+            label_size: 16.0,
+            title_size: 20.0,
+            readout_size: 16.0,
+            export_scale: 1.0,
+        }
+    }
 }
 #[derive(Copy, Clone)]
 pub enum Draw {
     Line(Pos2, Pos2, f32),
     Point(Pos2),
 }
+//draw list entries keep the depth of each line end for the depth buffer
+#[derive(Copy, Clone)]
+enum Prim {
+    Line((Pos2, f32), (Pos2, f32), f32),
+    Point(Pos2),
+}
+#[derive(Copy, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum Show {
     Real,
     Imag,
@@ -37,9 +203,48 @@ impl Show {
         matches!(self, Self::Complex | Self::Imag)
     }
 }
+const NEAR: f32 = 0.05;
+const HISTORY: usize = 256;
+const NUM_KEYS: [Key; 9] = [
+    Key::Num1,
+    Key::Num2,
+    Key::Num3,
+    Key::Num4,
+    Key::Num5,
+    Key::Num6,
+    Key::Num7,
+    Key::Num8,
+    Key::Num9,
+];
+type CacheView = (Vec3, f32, Vec2, f32);
+type DrawKey = (u64, View, Vec2, f32);
+type IndexKey = (u64, GraphMode, Show, usize, bool, Option<(Vec3, f32, Vec2)>);
+type DcCache = (Option<CacheView>, TextureHandle);
+type LegendRow = (Rect, usize, bool, Arc<Galley>);
+type DrawList = Vec<(f32, Prim, Color32)>;
+type Node = ((Pos2, f32), Vec3, bool, f32);
+type Projected = (Node, Option<(f32, Prim, Color32)>);
 pub struct Graph {
     data: Vec<GraphType>,
-    cache: Option<TextureHandle>,
+    series: Vec<Series>,
+    next_handle: usize,
+    legend: bool,
+    cache: HashMap<SeriesHandle, DcCache>,
+    resolution: usize,
+    discontinuity: Option<Discontinuity>,
+    perspective: bool,
+    fov: f32,
+    view_distance: f32,
+    rasterize: Option<usize>,
+    decimate: Option<usize>,
+    capacity: Option<usize>,
+    follow: Option<f32>,
+    newest: Option<f32>,
+    raster: Option<TextureHandle>,
+    raster_key: Option<(DrawKey, Option<Vec3>)>,
+    revision: u64,
+    draw_cache: Option<(DrawKey, Vec<Shape>, DrawList)>,
+    color_scheme: Box<dyn ColorScheme>,
     start: f32,
     end: f32,
     is_complex: bool,
@@ -74,12 +279,120 @@ pub struct Graph {
     graph_mode: GraphMode,
     is_3d: bool,
     last_interact: Option<Pos2>,
+    history: Vec<View>,
+    future: Vec<View>,
+    pending_view: Option<View>,
+    bookmarks: Vec<(String, View)>,
+    snap_coord: bool,
+    crosshair: bool,
+    measure: bool,
+    measure_points: Vec<Pos2>,
+    measure_series: Option<SeriesHandle>,
+    annotations: Vec<(AnnotationHandle, Annotation, Color32)>,
+    next_annotation: usize,
+    title: String,
+    labels: [(String, String); 3],
+    fonts: FontConfig,
+    pixels_per_point: f32,
+    follow_dark_mode: bool,
+    auto_colors: bool,
+    dark_mode: Option<bool>,
+    index: Option<(IndexKey, KdTree)>,
+}
+#[derive(Copy, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct View {
+    offset: Vec3,
+    zoom: f32,
+    theta: f32,
+    phi: f32,
+    box_size: f32,
+    slice: usize,
+    graph_mode: GraphMode,
+    show: Show,
+    is_3d: bool,
+}
+#[derive(Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Config {
+    data: Option<Vec<GraphType>>,
+    start: f32,
+    end: f32,
+    is_complex: bool,
+    view: View,
+    ignore_bounds: bool,
+    lines: bool,
+    anti_alias: bool,
+    color_depth: bool,
+    show_box: bool,
+    main_colors: Vec<Color32>,
+    alt_colors: Vec<Color32>,
+    axis_color: Color32,
+    axis_color_light: Color32,
+    background_color: Color32,
+    text_color: Color32,
+    scale_axis: bool,
+    disable_lines: bool,
+    disable_axis: bool,
+    disable_coord: bool,
+    view_x: bool,
+    bookmarks: Vec<(String, View)>,
+    annotations: Vec<(Annotation, Color32)>,
+    title: String,
+    labels: [(String, String); 3],
+    fonts: FontConfig,
+    discontinuity: Option<Discontinuity>,
+    perspective: bool,
+    fov: f32,
+    view_distance: f32,
+    rasterize: Option<usize>,
+    decimate: Option<usize>,
+    capacity: Option<usize>,
+    follow: Option<f32>,
+    legend: bool,
+    resolution: usize,
+    follow_dark_mode: bool,
+    auto_colors: bool,
+    snap_coord: bool,
+    crosshair: bool,
+    measure: bool,
+    measure_points: Vec<Pos2>,
+    measure_series: Option<usize>,
+    visibility: Vec<(bool, bool)>,
 }
 #[derive(Copy, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum Complex {
-    Real(f32),
-    Imag(f32),
-    Complex(f32, f32),
+    Real(#[cfg_attr(feature = "serde", serde(with = "float"))] f32),
+    Imag(#[cfg_attr(feature = "serde", serde(with = "float"))] f32),
+    Complex(
+        #[cfg_attr(feature = "serde", serde(with = "float"))] f32,
+        #[cfg_attr(feature = "serde", serde(with = "float"))] f32,
+    ),
+}
+//json has no nan or infinity, so non finite values are written as strings
+#[cfg(feature = "serde")]
+mod float {
+    use serde::{Deserialize, Deserializer, Serializer, de::Error};
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Repr {
+        Num(f32),
+        Str(String),
+    }
+    pub fn serialize<S: Serializer>(x: &f32, s: S) -> Result<S::Ok, S::Error> {
+        if x.is_finite() {
+            s.serialize_f32(*x)
+        } else {
+            s.serialize_str(&x.to_string())
+        }
+    }
+    pub fn deserialize<'de, D: Deserializer<'de>>(d: D) -> Result<f32, D::Error> {
+        match Repr::deserialize(d)? {
+            Repr::Num(x) => Ok(x),
+            Repr::Str(s) => s.parse().map_err(D::Error::custom),
+        }
+    }
 }
 impl Complex {
     fn to_options(self) -> (Option<f32>, Option<f32>) {
@@ -98,14 +411,48 @@ impl Complex {
         }
     }
 }
+impl std::fmt::Display for Complex {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let p = f.precision().unwrap_or(5);
+        match self {
+            Complex::Real(y) => write!(f, "{y:.p$}"),
+            Complex::Imag(z) => write!(f, "{z:.p$}i"),
+            Complex::Complex(y, z) => write!(
+                f,
+                "{y:.p$}{}{:.p$}i",
+                if z.is_sign_negative() { "-" } else { "+" },
+                z.abs()
+            ),
+        }
+    }
+}
 fn is_3d(data: &[GraphType]) -> bool {
-    data.iter()
-        .any(|c| matches!(c, GraphType::Width3D(_, _, _, _, _) | GraphType::Coord3D(_)))
+    data.iter().any(|c| {
+        matches!(
+            c,
+            GraphType::Width3D(_, _, _, _, _) | GraphType::Coord3D(_) | GraphType::Function3D(_)
+        )
+    })
 }
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug)]
+pub struct SeriesHandle(usize);
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug)]
+pub struct AnnotationHandle(usize);
 #[derive(Copy, Clone)]
+struct Series {
+    handle: SeriesHandle,
+    color: usize,
+    real: bool,
+    imag: bool,
+}
+#[derive(Copy, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Vec3 {
+    #[cfg_attr(feature = "serde", serde(with = "float"))]
     x: f32,
+    #[cfg_attr(feature = "serde", serde(with = "float"))]
     y: f32,
+    #[cfg_attr(feature = "serde", serde(with = "float"))]
     z: f32,
 }
 impl Vec3 {
@@ -154,9 +501,36 @@ impl Graph {
         let offset = Vec3::splat(0.0);
         let zoom = 1.0;
         let is_3d = is_3d(&data);
-        Self {
+        let theme = Theme::light();
+        let series = (0..data.len())
+            .map(|i| Series {
+                handle: SeriesHandle(i),
+                color: i,
+                real: true,
+                imag: true,
+            })
+            .collect();
+        let mut graph = Self {
+            next_handle: data.len(),
+            legend: false,
             data,
-            cache: None,
+            series,
+            cache: HashMap::new(),
+            resolution: 64,
+            discontinuity: None,
+            perspective: false,
+            fov: PI / 3.0,
+            view_distance: 3.0,
+            rasterize: None,
+            decimate: Some(65536),
+            capacity: None,
+            follow: None,
+            newest: None,
+            raster: None,
+            raster_key: None,
+            revision: 0,
+            draw_cache: None,
+            color_scheme: Box::new(color::Classic),
             start,
             end,
             offset,
@@ -177,26 +551,12 @@ impl Graph {
             anti_alias: true,
             lines: true,
             last_interact: None,
-            main_colors: vec![
-                Color32::from_rgb(255, 85, 85),
-                Color32::from_rgb(85, 85, 255),
-                Color32::from_rgb(255, 85, 255),
-                Color32::from_rgb(85, 255, 85),
-                Color32::from_rgb(85, 255, 255),
-                Color32::from_rgb(255, 255, 85),
-            ],
-            alt_colors: vec![
-                Color32::from_rgb(170, 0, 0),
-                Color32::from_rgb(0, 0, 170),
-                Color32::from_rgb(170, 0, 170),
-                Color32::from_rgb(0, 170, 0),
-                Color32::from_rgb(0, 170, 170),
-                Color32::from_rgb(170, 170, 0),
-            ],
-            axis_color: Color32::BLACK,
-            axis_color_light: Color32::LIGHT_GRAY,
-            text_color: Color32::BLACK,
-            background_color: Color32::WHITE,
+            main_colors: theme.main_colors,
+            alt_colors: theme.alt_colors,
+            axis_color: theme.axis_color,
+            axis_color_light: theme.axis_color_light,
+            text_color: theme.text_color,
+            background_color: theme.background_color,
             mouse_position: None,
             mouse_moved: false,
             scale_axis: false,
@@ -205,38 +565,294 @@ impl Graph {
             disable_coord: false,
             graph_mode: GraphMode::Normal,
             is_3d,
-        }
+            history: Vec::new(),
+            future: Vec::new(),
+            pending_view: None,
+            bookmarks: Vec::new(),
+            snap_coord: false,
+            crosshair: false,
+            measure: false,
+            measure_points: Vec::new(),
+            measure_series: None,
+            annotations: Vec::new(),
+            next_annotation: 0,
+            title: String::new(),
+            labels: Default::default(),
+            fonts: FontConfig::default(),
+            pixels_per_point: 1.0,
+            follow_dark_mode: false,
+            auto_colors: true,
+            dark_mode: None,
+            index: None,
+        };
+        graph.fill_colors();
+        graph
     }
     pub fn set_data(&mut self, data: Vec<GraphType>) {
+        self.series.clear();
+        for _ in 0..data.len() {
+            let series = self.next_series();
+            self.series.push(series);
+        }
         self.data = data;
-        self.cache = None;
-        self.is_3d = is_3d(&self.data);
+        self.cache.clear();
+        self.newest = None;
+        self.revision += 1;
+        self.fill_colors();
+        self.set_mode(self.graph_mode);
     }
     pub fn clear_data(&mut self) {
         self.data.clear();
-        self.cache = None;
+        self.series.clear();
+        self.cache.clear();
+        self.newest = None;
+        self.revision += 1;
     }
     pub fn push_data(&mut self, data: GraphType) {
+        self.insert_series(data);
+    }
+    pub fn insert_series(&mut self, data: GraphType) -> SeriesHandle {
+        let was_3d = is_3d(&self.data);
+        let series = self.next_series();
+        self.series.push(series);
         self.data.push(data);
-        self.is_3d = is_3d(&self.data);
+        self.changed(was_3d);
+        series.handle
+    }
+    pub fn update_series(&mut self, handle: SeriesHandle, data: GraphType) -> bool {
+        let Some(i) = self.series_index(handle) else {
+            return false;
+        };
+        let was_3d = is_3d(&self.data);
+        self.data[i] = data;
+        self.changed(was_3d);
+        true
+    }
+    pub fn remove_series(&mut self, handle: SeriesHandle) -> Option<GraphType> {
+        let i = self.series_index(handle)?;
+        let was_3d = is_3d(&self.data);
+        self.series.remove(i);
+        let data = self.data.remove(i);
+        self.changed(was_3d);
+        Some(data)
+    }
+    pub fn set_visible(&mut self, handle: SeriesHandle, visible: bool) -> bool {
+        self.set_part_visible(handle, Show::Complex, visible)
+    }
+    pub fn set_part_visible(&mut self, handle: SeriesHandle, part: Show, visible: bool) -> bool {
+        let Some(i) = self.series_index(handle) else {
+            return false;
+        };
+        if part.real() {
+            self.series[i].real = visible
+        }
+        if part.imag() {
+            self.series[i].imag = visible
+        }
+        self.revision += 1;
+        true
+    }
+    pub fn is_visible(&self, handle: SeriesHandle) -> Option<bool> {
+        let series = self.series[self.series_index(handle)?];
+        Some(series.real || series.imag)
+    }
+    pub fn set_legend(&mut self, legend: bool) {
+        self.legend = legend
+    }
+    fn toggle_part(&mut self, n: usize, imag: bool) {
+        let Some(series) = self.series.get_mut(n) else {
+            return;
+        };
+        if imag {
+            series.imag = !series.imag
+        } else {
+            series.real = !series.real
+        }
+        self.revision += 1;
+    }
+    //the parts of a series left to draw after the global and per series toggles
+    fn series_show(&self, n: usize) -> Option<Show> {
+        let series = &self.series[n];
+        match (
+            self.show.real() && series.real,
+            self.show.imag() && series.imag,
+        ) {
+            (true, true) => Some(Show::Complex),
+            (true, false) => Some(Show::Real),
+            (false, true) => Some(Show::Imag),
+            (false, false) => None,
+        }
+    }
+    pub fn series_index(&self, handle: SeriesHandle) -> Option<usize> {
+        self.series.iter().position(|s| s.handle == handle)
+    }
+    pub fn get_handles(&self) -> impl Iterator<Item = SeriesHandle> {
+        self.series.iter().map(|s| s.handle)
+    }
+    //colour slots are kept per series so removing one does not recolour the rest
+    fn next_series(&mut self) -> Series {
+        let color = (0..)
+            .find(|c| self.series.iter().all(|s| s.color != *c))
+            .unwrap();
+        self.next_handle += 1;
+        Series {
+            handle: SeriesHandle(self.next_handle - 1),
+            color,
+            real: true,
+            imag: true,
+        }
+    }
+    //only re-derives is_3d when the data gained or lost its last 3d series, so a
+    //2d view of 3d data chosen through set_mode or the keybinds is kept
+    fn changed(&mut self, was_3d: bool) {
+        self.cache.clear();
+        self.revision += 1;
+        self.fill_colors();
+        if is_3d(&self.data) != was_3d {
+            self.set_mode(self.graph_mode)
+        }
+    }
+    pub fn append_values(&mut self, series: usize, values: &[Complex]) -> bool {
+        let Some(GraphType::Width(data, start, end)) = self.data.get_mut(series) else {
+            return false;
+        };
+        let step = (*end - *start) / (data.len().max(2) - 1) as f32;
+        data.extend_from_slice(values);
+        *end += step * values.len() as f32;
+        let end = *end;
+        self.trim(series, true);
+        self.newest = Some(self.newest.map_or(end, |n| n.max(end)));
+        self.appended();
+        true
+    }
+    pub fn append_series_values(&mut self, handle: SeriesHandle, values: &[Complex]) -> bool {
+        self.series_index(handle)
+            .is_some_and(|i| self.append_values(i, values))
+    }
+    pub fn append_points(&mut self, series: usize, points: &[(f32, Complex)]) -> bool {
+        let Some(GraphType::Coord(data)) = self.data.get_mut(series) else {
+            return false;
+        };
+        data.extend_from_slice(points);
+        self.trim(series, true);
+        for (x, _) in points {
+            self.newest = Some(self.newest.map_or(*x, |n| n.max(*x)));
+        }
+        self.appended();
+        true
+    }
+    pub fn append_series_points(
+        &mut self,
+        handle: SeriesHandle,
+        points: &[(f32, Complex)],
+    ) -> bool {
+        self.series_index(handle)
+            .is_some_and(|i| self.append_points(i, points))
+    }
+    //appends trim an eighth of the capacity at once so a full buffer is not shifted
+    //on every sample
+    fn trim(&mut self, series: usize, batch: bool) {
+        let Some(capacity) = self.capacity else {
+            return;
+        };
+        let keep = if batch {
+            capacity - capacity / 8
+        } else {
+            capacity
+        };
+        match &mut self.data[series] {
+            GraphType::Width(data, start, end) if data.len() > capacity => {
+                let step = (*end - *start) / (data.len() - 1) as f32;
+                let excess = data.len() - keep;
+                data.drain(..excess);
+                *start += step * excess as f32;
+            }
+            GraphType::Coord(data) if data.len() > capacity => {
+                data.drain(..data.len() - keep);
+            }
+            _ => {}
+        }
+    }
+    fn appended(&mut self) {
+        self.cache.clear();
+        self.revision += 1;
+    }
+    pub fn set_capacity(&mut self, capacity: Option<usize>) {
+        self.capacity = capacity.filter(|c| *c > 0);
+        for series in 0..self.data.len() {
+            self.trim(series, false)
+        }
+        self.appended();
+    }
+    pub fn set_follow(&mut self, window: Option<f32>) {
+        self.follow = window.filter(|w| *w > 0.0)
+    }
+    //keeps the newest appended x on the right edge with window units visible
+    fn follow_newest(&mut self) {
+        if let (Some(window), Some(newest)) = (self.follow, self.newest)
+            && !self.is_3d
+            && self.screen.x > 0.0
+        {
+            self.zoom = (self.end - self.start) / window;
+            self.offset.x = self.screen.x / self.zoom
+                - newest * self.screen.x / (self.end - self.start)
+                - self.screen_offset.x;
+        }
+    }
+    pub fn set_resolution(&mut self, resolution: usize) {
+        self.resolution = resolution.max(2);
+        self.revision += 1;
+    }
+    pub fn set_discontinuity(&mut self, discontinuity: Option<Discontinuity>) {
+        self.discontinuity = discontinuity;
+        self.revision += 1;
+    }
+    pub fn set_perspective(&mut self, perspective: bool) {
+        self.perspective = perspective;
+        self.revision += 1;
+    }
+    pub fn set_fov(&mut self, fov: f32) {
+        self.fov = fov.clamp(0.01, PI - 0.01);
+        self.revision += 1;
+    }
+    pub fn set_view_distance(&mut self, distance: f32) {
+        self.view_distance = distance.max(1.1);
+        self.revision += 1;
+    }
+    pub fn set_rasterize(&mut self, threshold: Option<usize>) {
+        self.rasterize = threshold;
+        self.revision += 1;
+        if threshold.is_none() {
+            self.raster = None
+        }
+    }
+    pub fn set_decimation(&mut self, threshold: Option<usize>) {
+        self.decimate = threshold;
+        self.revision += 1;
     }
     pub fn set_lines(&mut self, lines: bool) {
-        self.lines = lines
+        self.lines = lines;
+        self.revision += 1;
     }
     pub fn set_anti_alias(&mut self, anti_alias: bool) {
-        self.anti_alias = anti_alias
+        self.anti_alias = anti_alias;
+        self.revision += 1;
     }
     pub fn set_main_colors(&mut self, colors: Vec<Color32>) {
-        self.main_colors = colors
+        self.main_colors = colors;
+        self.revision += 1;
     }
     pub fn set_alt_colors(&mut self, colors: Vec<Color32>) {
-        self.alt_colors = colors
+        self.alt_colors = colors;
+        self.revision += 1;
     }
     pub fn set_axis_color(&mut self, color: Color32) {
-        self.axis_color = color
+        self.axis_color = color;
+        self.revision += 1;
     }
     pub fn set_axis_color_light(&mut self, color: Color32) {
-        self.axis_color_light = color
+        self.axis_color_light = color;
+        self.revision += 1;
     }
     pub fn set_background_color(&mut self, color: Color32) {
         self.background_color = color
@@ -244,18 +860,170 @@ impl Graph {
     pub fn set_text_color(&mut self, color: Color32) {
         self.text_color = color
     }
+    pub fn set_theme(&mut self, theme: Theme) {
+        self.main_colors = theme.main_colors;
+        self.alt_colors = theme.alt_colors;
+        self.axis_color = theme.axis_color;
+        self.axis_color_light = theme.axis_color_light;
+        self.background_color = theme.background_color;
+        self.text_color = theme.text_color;
+        self.revision += 1;
+        self.fill_colors();
+    }
+    pub fn get_theme(&self) -> Theme {
+        Theme {
+            background_color: self.background_color,
+            axis_color: self.axis_color,
+            axis_color_light: self.axis_color_light,
+            text_color: self.text_color,
+            main_colors: self.main_colors.clone(),
+            alt_colors: self.alt_colors.clone(),
+        }
+    }
+    pub fn generate_colors(&mut self, n: usize) {
+        let (l, _, _) = color::color_to_oklch(self.background_color);
+        self.main_colors = palette(n, 0.7, 0.15);
+        self.alt_colors = alt_palette(&self.main_colors, if l < 0.5 { 0.15 } else { -0.2 });
+        self.revision += 1;
+    }
+    pub fn set_auto_colors(&mut self, auto: bool) {
+        self.auto_colors = auto;
+        self.fill_colors();
+    }
+    fn fill_colors(&mut self) {
+        let n = self.series.iter().map(|s| s.color + 1).max().unwrap_or(0);
+        if self.auto_colors && n > self.main_colors.len() {
+            self.generate_colors(n)
+        }
+    }
+    pub fn set_color_scheme<T: ColorScheme + 'static>(&mut self, scheme: T) {
+        self.color_scheme = Box::new(scheme);
+        self.cache.clear();
+        self.revision += 1;
+    }
+    pub fn set_follow_dark_mode(&mut self, follow: bool) {
+        self.follow_dark_mode = follow;
+        self.dark_mode = None;
+    }
     pub fn set_scale_axis(&mut self, scale: bool) {
-        self.scale_axis = scale
+        self.scale_axis = scale;
+        self.revision += 1;
     }
     pub fn disable_lines(&mut self, disable: bool) {
-        self.disable_lines = disable
+        self.disable_lines = disable;
+        self.revision += 1;
     }
     pub fn disable_axis(&mut self, disable: bool) {
-        self.disable_axis = disable
+        self.disable_axis = disable;
+        self.revision += 1;
     }
     pub fn disable_coord(&mut self, disable: bool) {
         self.disable_coord = disable
     }
+    pub fn set_snap_coord(&mut self, snap: bool) {
+        self.snap_coord = snap
+    }
+    pub fn set_crosshair(&mut self, crosshair: bool) {
+        self.crosshair = crosshair
+    }
+    pub fn set_measure(&mut self, measure: bool) {
+        self.measure = measure;
+        self.measure_points.clear();
+    }
+    pub fn set_measure_series(&mut self, series: Option<SeriesHandle>) {
+        self.measure_series = series
+    }
+    pub fn get_measure(&self) -> Option<(Pos2, Pos2)> {
+        if let [a, b] = self.measure_points[..] {
+            Some((a, b))
+        } else {
+            None
+        }
+    }
+    pub fn integrate(&self, series: usize, x0: f32, x1: f32) -> Option<Complex> {
+        let mut pts: Vec<(f32, Complex)> = match self.data.get(series)? {
+            GraphType::Width(data, _, _) if data.len() < 2 => return None,
+            GraphType::Width(data, start, end) => data
+                .iter()
+                .enumerate()
+                .map(|(i, y)| {
+                    let x = (i as f32 / (data.len() - 1) as f32 - 0.5) * (end - start)
+                        + (start + end) / 2.0;
+                    (x, *y)
+                })
+                .collect(),
+            GraphType::Coord(data) => data.clone(),
+            GraphType::Function(f) => (0..=1024)
+                .map(|i| {
+                    let x = x0 + (x1 - x0) * i as f32 / 1024.0;
+                    (x, f(x))
+                })
+                .collect(),
+            _ => return None,
+        };
+        pts.sort_by(|a, b| a.0.total_cmp(&b.0));
+        let (lo, hi, sign) = if x0 <= x1 {
+            (x0, x1, 1.0)
+        } else {
+            (x1, x0, -1.0)
+        };
+        let (mut re, mut im) = (None, None);
+        for w in pts.windows(2) {
+            let ((xa, a), (xb, b)) = (w[0], w[1]);
+            let (u, v) = (xa.max(lo), xb.min(hi));
+            if u >= v {
+                continue;
+            }
+            let (ya, za) = a.to_options();
+            let (yb, zb) = b.to_options();
+            let area = |sum: &mut Option<f32>, fa: Option<f32>, fb: Option<f32>| {
+                if let (Some(fa), Some(fb)) = (fa, fb)
+                    && fa.is_finite()
+                    && fb.is_finite()
+                {
+                    let f = |x: f32| fa + (fb - fa) * (x - xa) / (xb - xa);
+                    *sum = Some(sum.unwrap_or(0.0) + (v - u) * (f(u) + f(v)) / 2.0)
+                }
+            };
+            area(&mut re, ya, yb);
+            area(&mut im, za, zb);
+        }
+        if re.is_none() && im.is_none() {
+            return None;
+        }
+        Some(Complex::from(re.map(|r| sign * r), im.map(|i| sign * i)))
+    }
+    pub fn add_annotation(&mut self, annotation: Annotation, color: Color32) -> AnnotationHandle {
+        let handle = AnnotationHandle(self.next_annotation);
+        self.next_annotation += 1;
+        self.annotations.push((handle, annotation, color));
+        self.revision += 1;
+        handle
+    }
+    pub fn remove_annotation(&mut self, handle: AnnotationHandle) -> Option<Annotation> {
+        let i = self.annotations.iter().position(|(h, _, _)| *h == handle)?;
+        self.revision += 1;
+        Some(self.annotations.remove(i).1)
+    }
+    pub fn clear_annotations(&mut self) {
+        self.annotations.clear();
+        self.revision += 1;
+    }
+    pub fn set_title(&mut self, title: &str) {
+        self.title = title.to_string()
+    }
+    pub fn set_x_label(&mut self, label: &str, unit: Option<&str>) {
+        self.labels[0] = (label.to_string(), unit.unwrap_or_default().to_string())
+    }
+    pub fn set_y_label(&mut self, label: &str, unit: Option<&str>) {
+        self.labels[1] = (label.to_string(), unit.unwrap_or_default().to_string())
+    }
+    pub fn set_z_label(&mut self, label: &str, unit: Option<&str>) {
+        self.labels[2] = (label.to_string(), unit.unwrap_or_default().to_string())
+    }
+    pub fn set_fonts(&mut self, fonts: FontConfig) {
+        self.fonts = fonts
+    }
     pub fn set_offset(&mut self, offset: Vec3) {
         self.offset = offset
     }
@@ -268,7 +1036,265 @@ impl Graph {
         }
         self.graph_mode = mode;
     }
+    pub fn get_config(&self, data: bool) -> Config {
+        let saved = |(d, _): &(&GraphType, &Series)| {
+            !data || !matches!(d, GraphType::Function(_) | GraphType::Function3D(_))
+        };
+        let series = self.data.iter().zip(&self.series).filter(saved);
+        //handles are not stable across a load, so the measured series is saved by position
+        let measure_series = series
+            .clone()
+            .position(|(_, s)| Some(s.handle) == self.measure_series);
+        Config {
+            data: data.then(|| series.clone().map(|(d, _)| d.clone()).collect()),
+            visibility: series.map(|(_, s)| (s.real, s.imag)).collect(),
+            start: self.start,
+            end: self.end,
+            is_complex: self.is_complex,
+            view: self.get_view(),
+            ignore_bounds: self.ignore_bounds,
+            lines: self.lines,
+            anti_alias: self.anti_alias,
+            color_depth: self.color_depth,
+            show_box: self.show_box,
+            main_colors: self.main_colors.clone(),
+            alt_colors: self.alt_colors.clone(),
+            axis_color: self.axis_color,
+            axis_color_light: self.axis_color_light,
+            background_color: self.background_color,
+            text_color: self.text_color,
+            scale_axis: self.scale_axis,
+            disable_lines: self.disable_lines,
+            disable_axis: self.disable_axis,
+            disable_coord: self.disable_coord,
+            view_x: self.view_x,
+            bookmarks: self.bookmarks.clone(),
+            annotations: self
+                .annotations
+                .iter()
+                .map(|(_, a, c)| (a.clone(), *c))
+                .collect(),
+            title: self.title.clone(),
+            labels: self.labels.clone(),
+            fonts: self.fonts.clone(),
+            discontinuity: self.discontinuity,
+            perspective: self.perspective,
+            fov: self.fov,
+            view_distance: self.view_distance,
+            rasterize: self.rasterize,
+            decimate: self.decimate,
+            capacity: self.capacity,
+            follow: self.follow,
+            legend: self.legend,
+            resolution: self.resolution,
+            follow_dark_mode: self.follow_dark_mode,
+            auto_colors: self.auto_colors,
+            snap_coord: self.snap_coord,
+            crosshair: self.crosshair,
+            measure: self.measure,
+            measure_points: self.measure_points.clone(),
+            measure_series,
+        }
+    }
+    pub fn set_config(&mut self, config: Config) {
+        if let Some(data) = config.data {
+            self.set_data(data);
+        }
+        self.start = config.start;
+        self.end = config.end;
+        self.is_complex = config.is_complex;
+        self.ignore_bounds = config.ignore_bounds;
+        self.lines = config.lines;
+        self.anti_alias = config.anti_alias;
+        self.color_depth = config.color_depth;
+        self.show_box = config.show_box;
+        self.main_colors = config.main_colors;
+        self.alt_colors = config.alt_colors;
+        self.axis_color = config.axis_color;
+        self.axis_color_light = config.axis_color_light;
+        self.background_color = config.background_color;
+        self.text_color = config.text_color;
+        self.scale_axis = config.scale_axis;
+        self.disable_lines = config.disable_lines;
+        self.disable_axis = config.disable_axis;
+        self.disable_coord = config.disable_coord;
+        self.view_x = config.view_x;
+        self.bookmarks = config.bookmarks;
+        self.clear_annotations();
+        for (annotation, color) in config.annotations {
+            self.add_annotation(annotation, color);
+        }
+        self.title = config.title;
+        self.labels = config.labels;
+        self.fonts = config.fonts;
+        self.discontinuity = config.discontinuity;
+        self.perspective = config.perspective;
+        self.set_fov(config.fov);
+        self.set_view_distance(config.view_distance);
+        self.rasterize = config.rasterize;
+        self.decimate = config.decimate;
+        self.set_capacity(config.capacity);
+        self.follow = config.follow;
+        self.legend = config.legend;
+        self.set_resolution(config.resolution);
+        self.follow_dark_mode = config.follow_dark_mode;
+        self.dark_mode = None;
+        self.set_auto_colors(config.auto_colors);
+        self.snap_coord = config.snap_coord;
+        self.crosshair = config.crosshair;
+        self.measure = config.measure;
+        self.measure_points = config.measure_points;
+        self.measure_series = config
+            .measure_series
+            .and_then(|i| self.series.get(i))
+            .map(|s| s.handle);
+        for (series, (real, imag)) in self.series.iter_mut().zip(config.visibility) {
+            series.real = real;
+            series.imag = imag;
+        }
+        self.set_view(config.view);
+        self.clear_history();
+        self.cache.clear();
+        self.revision += 1;
+    }
+    #[cfg(feature = "serde")]
+    //closure backed series cannot be saved, returns how many were left out
+    pub fn save(&self, data: bool) -> eyre::Result<(String, usize)> {
+        let skipped = if data {
+            self.data
+                .iter()
+                .filter(|d| matches!(d, GraphType::Function(_) | GraphType::Function3D(_)))
+                .count()
+        } else {
+            0
+        };
+        Ok((
+            serde_json::to_string_pretty(&self.get_config(data))?,
+            skipped,
+        ))
+    }
+    #[cfg(feature = "serde")]
+    pub fn load(&mut self, config: &str) -> eyre::Result<()> {
+        self.set_config(serde_json::from_str(config)?);
+        Ok(())
+    }
+    pub fn get_view(&self) -> View {
+        View {
+            offset: self.offset,
+            zoom: self.zoom,
+            theta: self.theta,
+            phi: self.phi,
+            box_size: self.box_size,
+            slice: self.slice,
+            graph_mode: self.graph_mode,
+            show: self.show,
+            is_3d: self.is_3d,
+        }
+    }
+    pub fn set_view(&mut self, view: View) {
+        if self.graph_mode != view.graph_mode {
+            self.cache.clear();
+        }
+        self.offset = view.offset;
+        self.zoom = view.zoom;
+        self.theta = view.theta;
+        self.phi = view.phi;
+        self.box_size = view.box_size;
+        self.slice = view.slice;
+        self.graph_mode = view.graph_mode;
+        self.show = view.show;
+        self.is_3d = view.is_3d;
+    }
+    pub fn push_view(&mut self) {
+        self.flush_view();
+        let view = self.get_view();
+        if self.history.last() != Some(&view) {
+            self.push_history(view);
+        }
+        self.future.clear();
+    }
+    pub fn undo(&mut self) -> bool {
+        self.flush_view();
+        if let Some(view) = self.history.pop() {
+            self.future.push(self.get_view());
+            self.set_view(view);
+            true
+        } else {
+            false
+        }
+    }
+    pub fn redo(&mut self) -> bool {
+        self.flush_view();
+        if let Some(view) = self.future.pop() {
+            self.push_history(self.get_view());
+            self.set_view(view);
+            true
+        } else {
+            false
+        }
+    }
+    pub fn clear_history(&mut self) {
+        self.history.clear();
+        self.future.clear();
+        self.pending_view = None;
+    }
+    pub fn save_bookmark(&mut self, name: &str) {
+        let view = self.get_view();
+        if let Some((_, v)) = self.bookmarks.iter_mut().find(|(n, _)| n == name) {
+            *v = view
+        } else {
+            self.bookmarks.push((name.to_string(), view))
+        }
+    }
+    pub fn load_bookmark(&mut self, name: &str) -> bool {
+        if let Some((_, view)) = self.bookmarks.iter().find(|(n, _)| n == name) {
+            let view = *view;
+            self.push_view();
+            self.set_view(view);
+            true
+        } else {
+            false
+        }
+    }
+    pub fn remove_bookmark(&mut self, name: &str) -> bool {
+        let len = self.bookmarks.len();
+        self.bookmarks.retain(|(n, _)| n != name);
+        len != self.bookmarks.len()
+    }
+    pub fn get_bookmarks(&self) -> impl Iterator<Item = &str> {
+        self.bookmarks.iter().map(|(n, _)| n.as_str())
+    }
+    fn flush_view(&mut self) {
+        if let Some(view) = self.pending_view.take() {
+            self.push_history(view);
+            self.future.clear();
+        }
+    }
+    fn push_history(&mut self, view: View) {
+        if self.history.len() >= HISTORY {
+            self.history.remove(0);
+        }
+        self.history.push(view);
+    }
+    //a continuous interaction only becomes one history entry, the view from before it
+    //is pushed once a frame passes without the view changing
+    fn record_view(&mut self, before: View) {
+        if self.get_view() != before {
+            if self.pending_view.is_none() {
+                self.pending_view = Some(before)
+            }
+        } else {
+            self.flush_view()
+        }
+    }
     pub fn update(&mut self, ctx: &Context) {
+        if self.follow_dark_mode {
+            let dark = ctx.style().visuals.dark_mode;
+            if self.dark_mode != Some(dark) {
+                self.dark_mode = Some(dark);
+                self.set_theme(if dark { Theme::dark() } else { Theme::light() });
+            }
+        }
         CentralPanel::default()
             .frame(egui::Frame::default().fill(self.background_color))
             .show(ctx, |ui| self.plot_main(ctx, ui));
@@ -276,7 +1302,12 @@ impl Graph {
     fn plot_main(&mut self, ctx: &Context, ui: &Ui) {
         let painter = ui.painter();
         let rect = ctx.available_rect();
+        let before = (self.offset, self.zoom);
         self.keybinds(ui);
+        if (self.offset, self.zoom) != before {
+            self.follow = None
+        }
+        self.pixels_per_point = ctx.pixels_per_point();
         self.screen = Vec2::new(rect.width(), rect.height());
         self.delta = if self.is_3d {
             self.screen.x.min(self.screen.y)
@@ -287,45 +1318,518 @@ impl Graph {
             self.screen.x / 2.0 - self.delta * (self.start + self.end) / 2.0,
             self.screen.y / 2.0,
         );
+        self.follow_newest();
+        let key = (
+            self.revision,
+            self.get_view(),
+            self.screen,
+            self.pixels_per_point,
+        );
+        let (key, shapes, mut pts) = match self.draw_cache.take() {
+            Some(cache) if cache.0 == key => cache,
+            _ => {
+                let mut shapes = Vec::new();
+                let pts = self.plot(&mut shapes, ui);
+                let key = (key.0, self.get_view(), key.2, key.3);
+                (key, shapes, pts)
+            }
+        };
         if !self.is_3d {
             self.write_axis(painter);
-            self.plot(painter, ui);
+            painter.extend(shapes.iter().cloned());
+            self.write_annotations(painter);
         } else {
-            let mut pts = self.plot(painter, ui);
-            pts.extend(self.write_axis_3d(painter));
-            pts.sort_by(|a, b| a.0.total_cmp(&b.0));
-            for (_, a, c) in pts.into_iter() {
-                match a {
-                    Draw::Line(a, b, t) => {
-                        painter.line_segment([a, b], Stroke::new(t, c));
-                    }
-                    Draw::Point(a) => {
-                        let rect = Rect::from_center_size(a, Vec2::splat(3.0));
-                        painter.rect_filled(rect, 0.0, c);
+            painter.extend(shapes.iter().cloned());
+            let mut extra = self.write_axis_3d(painter);
+            extra.extend(self.write_annotations_3d());
+            let pick = if self.snap_coord && !self.disable_coord {
+                self.pick_3d(&pts)
+            } else {
+                None
+            };
+            if let Some((_, v)) = pick {
+                extra.extend(self.crosshair_3d(v));
+            }
+            if self.rasterize.is_some_and(|n| pts.len() + extra.len() > n) {
+                let raster_key = Some((key, pick.map(|(_, v)| v)));
+                if self.raster.is_none() || self.raster_key != raster_key {
+                    self.draw_raster(ctx, pts.iter().chain(&extra));
+                    self.raster_key = raster_key;
+                }
+                painter.image(
+                    self.raster.as_ref().unwrap().id(),
+                    Rect::from_min_size(Pos2::new(0.0, 0.0), self.screen),
+                    Rect::from_min_max(Pos2::new(0.0, 0.0), Pos2::new(1.0, 1.0)),
+                    Color32::WHITE,
+                );
+            } else {
+                //the painter needs back to front order, the depth buffer does not, so the
+                //list is only sorted here and stays sorted while the cache holds it
+                if !pts.is_sorted_by(|a, b| a.0.total_cmp(&b.0).is_le()) {
+                    pts.sort_by(|a, b| a.0.total_cmp(&b.0));
+                }
+                extra.sort_by(|a, b| a.0.total_cmp(&b.0));
+                let mut extra = extra.into_iter().peekable();
+                for p in &pts {
+                    while let Some(e) = extra.next_if(|e| e.0 <= p.0) {
+                        draw_3d(painter, &e)
                     }
+                    draw_3d(painter, p)
+                }
+                for e in extra {
+                    draw_3d(painter, &e)
                 }
             }
+            self.write_annotation_text_3d(painter);
+            if let Some((p, v)) = pick {
+                painter.text(
+                    p + Vec2::new(8.0, -8.0),
+                    Align2::LEFT_BOTTOM,
+                    format!("{:.5}\n{:.5}\n{:.5}", v.x, v.y, v.z - self.offset.z),
+                    self.font(self.fonts.readout_size),
+                    self.text_color,
+                );
+            }
         }
+        self.write_title(painter);
+        self.write_legend(painter);
         if !self.is_3d {
-            self.write_coord(painter);
+            self.write_labels(painter);
+            if self.snap_coord {
+                self.write_snap(painter);
+            }
+            if self.measure {
+                self.write_measure(painter);
+            }
+            if self.crosshair {
+                self.write_crosshair(painter);
+            } else {
+                self.write_coord(painter);
+            }
         } else {
             self.write_angle(painter);
         }
+        self.draw_cache = Some((key, shapes, pts));
     }
-    fn write_coord(&self, painter: &Painter) {
-        if self.mouse_moved && !self.disable_coord {
-            if let Some(pos) = self.mouse_position {
-                let p = self.to_coord(pos);
+    fn font(&self, size: f32) -> FontId {
+        FontId::new(size * self.fonts.export_scale, self.fonts.family.clone())
+    }
+    fn label(&self, axis: usize) -> Option<String> {
+        let (label, unit) = &self.labels[axis];
+        if label.is_empty() {
+            None
+        } else if unit.is_empty() {
+            Some(label.clone())
+        } else {
+            Some(format!("{label} ({unit})"))
+        }
+    }
+    //rows are sized from the laid out text so clicks match what is drawn at any scale
+    fn legend_rows(&self, painter: &Painter) -> Vec<LegendRow> {
+        if !self.legend {
+            return Vec::new();
+        }
+        let scale = self.fonts.export_scale;
+        let mut texts = Vec::new();
+        for (n, series) in self.series.iter().enumerate() {
+            for imag in [false, true] {
+                if imag && !self.is_complex {
+                    continue;
+                }
+                let visible = if imag { series.imag } else { series.real };
+                let text = if !self.is_complex {
+                    n.to_string()
+                } else if imag {
+                    format!("{n} im")
+                } else {
+                    format!("{n} re")
+                };
+                let color = if visible {
+                    self.text_color
+                } else {
+                    self.text_color.gamma_multiply(0.5)
+                };
+                let font = self.font(self.fonts.readout_size);
+                texts.push((n, imag, painter.layout_no_wrap(text, font, color)));
+            }
+        }
+        let w = texts.iter().map(|t| t.2.size().x).fold(0.0, f32::max) + 24.0 * scale;
+        let mut y = 8.0 * scale;
+        texts
+            .into_iter()
+            .map(|(n, imag, galley)| {
+                let h = galley.size().y.max(8.0 * scale) + 4.0 * scale;
+                let min = Pos2::new(self.screen.x - 8.0 * scale - w, y);
+                y += h;
+                (Rect::from_min_size(min, Vec2::new(w, h)), n, imag, galley)
+            })
+            .collect()
+    }
+    fn write_legend(&self, painter: &Painter) {
+        let rows = self.legend_rows(painter);
+        if let (Some(first), Some(last)) = (rows.first(), rows.last()) {
+            painter.rect_filled(first.0.union(last.0), 0.0, self.background_color);
+        }
+        let scale = self.fonts.export_scale;
+        for (rect, n, imag, galley) in rows {
+            let series = &self.series[n];
+            let (colors, visible) = if imag {
+                (&self.alt_colors, series.imag)
+            } else {
+                (&self.main_colors, series.real)
+            };
+            let color = colors[series.color % colors.len()];
+            let center = rect.left_center() + Vec2::new(8.0 * scale, 0.0);
+            if visible {
+                painter.circle_filled(center, 4.0 * scale, color);
+            } else {
+                painter.circle_stroke(center, 4.0 * scale, Stroke::new(scale, color));
+            }
+            let pos = Pos2::new(center.x + 8.0 * scale, center.y - galley.size().y / 2.0);
+            painter.galley(pos, galley, self.text_color);
+        }
+    }
+    fn write_title(&self, painter: &Painter) {
+        if !self.title.is_empty() {
+            painter.text(
+                Pos2::new(self.screen.x / 2.0, 0.0),
+                Align2::CENTER_TOP,
+                &self.title,
+                self.font(self.fonts.title_size),
+                self.text_color,
+            );
+        }
+    }
+    fn write_labels(&self, painter: &Painter) {
+        if let Some(label) = self.label(0) {
+            painter.text(
+                Pos2::new(self.screen.x / 2.0, self.screen.y),
+                Align2::CENTER_BOTTOM,
+                label,
+                self.font(self.fonts.label_size),
+                self.text_color,
+            );
+        }
+        if let Some(label) = self.label(1) {
+            let galley =
+                painter.layout_no_wrap(label, self.font(self.fonts.label_size), self.text_color);
+            let pos = Pos2::new(0.0, (self.screen.y + galley.size().x) / 2.0);
+            painter.add(TextShape::new(pos, galley, self.text_color).with_angle(-PI / 2.0));
+        }
+    }
+    fn write_annotations(&self, painter: &Painter) {
+        let scale = self.screen.x * self.zoom / (self.end - self.start);
+        for (_, annotation, color) in &self.annotations {
+            let stroke = Stroke::new(1.0, *color);
+            match annotation {
+                Annotation::Text(p, text) => {
+                    painter.text(
+                        self.to_screen(p.x, p.y),
+                        Align2::LEFT_BOTTOM,
+                        text,
+                        self.font(self.fonts.label_size),
+                        *color,
+                    );
+                }
+                Annotation::Arrow(a, b) => {
+                    let a = self.to_screen(a.x, a.y);
+                    let b = self.to_screen(b.x, b.y);
+                    painter.arrow(a, b - a, stroke);
+                }
+                Annotation::VLine(x) => {
+                    let x = self.to_screen(*x, 0.0).x;
+                    painter.vline(x, Rangef::new(0.0, self.screen.y), stroke);
+                }
+                Annotation::HLine(y) => {
+                    let y = self.to_screen(0.0, *y).y;
+                    painter.hline(Rangef::new(0.0, self.screen.x), y, stroke);
+                }
+                Annotation::Span(a, b) => {
+                    let a = self.to_screen(*a, 0.0).x;
+                    let b = self.to_screen(*b, 0.0).x;
+                    painter.rect_filled(
+                        Rect::from_x_y_ranges(Rangef::new(a, b), Rangef::new(0.0, self.screen.y)),
+                        0.0,
+                        color.gamma_multiply(0.25),
+                    );
+                }
+                Annotation::Circle(p, r) => {
+                    painter.circle_stroke(self.to_screen(p.x, p.y), r * scale, stroke);
+                }
+            }
+        }
+    }
+    fn project_annotation(&self, p: &Vec3) -> (Pos2, f32) {
+        self.vec3_to_pos_depth(Vec3::new(p.x, p.y, p.z + self.offset.z))
+    }
+    //text is painted once the depth sorted geometry is down so the plot cannot cover it
+    fn write_annotation_text_3d(&self, painter: &Painter) {
+        for (_, annotation, color) in &self.annotations {
+            if let Annotation::Text(p, text) = annotation {
                 painter.text(
-                    Pos2::new(0.0, self.screen.y),
+                    self.project_annotation(p).0,
                     Align2::LEFT_BOTTOM,
-                    format!("{{{0:.5},{1:.5}}}", p.x, p.y),
-                    FontId::monospace(16.0),
-                    self.text_color,
+                    text,
+                    self.font(self.fonts.label_size),
+                    *color,
                 );
             }
         }
     }
+    fn write_annotations_3d(&self) -> Vec<(f32, Prim, Color32)> {
+        let mut lines = Vec::new();
+        let project = |p: &Vec3| self.project_annotation(p);
+        for (_, annotation, color) in &self.annotations {
+            match annotation {
+                Annotation::Arrow(a, b) => {
+                    let (a, da) = project(a);
+                    let (b, db) = project(b);
+                    let d = (da + db) / 2.0;
+                    lines.push((d, Prim::Line((a, da), (b, db), 1.0), *color));
+                    let dir = (b - a).normalized() * 8.0;
+                    for rot in [Rot2::from_angle(PI / 6.0), Rot2::from_angle(-PI / 6.0)] {
+                        lines.push((db, Prim::Line((b, db), (b - rot * dir, db), 1.0), *color));
+                    }
+                }
+                Annotation::Circle(p, r) => {
+                    let (c, d) = project(p);
+                    let r = r * self.delta / self.box_size;
+                    let point = |i: usize| c + Vec2::angled(TAU * i as f32 / 32.0) * r;
+                    for i in 0..32 {
+                        lines.push((d, Prim::Line((point(i), d), (point(i + 1), d), 1.0), *color));
+                    }
+                }
+                Annotation::Text(_, _)
+                | Annotation::VLine(_)
+                | Annotation::HLine(_)
+                | Annotation::Span(_, _) => {}
+            }
+        }
+        lines
+    }
+    fn write_coord(&self, painter: &Painter) {
+        if self.mouse_moved
+            && !self.disable_coord
+            && let Some(pos) = self.mouse_position
+        {
+            let p = self.to_coord(pos);
+            painter.text(
+                Pos2::new(0.0, self.screen.y),
+                Align2::LEFT_BOTTOM,
+                format!("{{{0:.5},{1:.5}}}", p.x, p.y),
+                self.font(self.fonts.readout_size),
+                self.text_color,
+            );
+        }
+    }
+    fn write_snap(&mut self, painter: &Painter) {
+        if !self.mouse_moved || self.disable_coord {
+            return;
+        }
+        let Some(pos) = self.mouse_position else {
+            return;
+        };
+        //sampled functions depend on the visible range, plain data only on the revision
+        let sampled = self
+            .data
+            .iter()
+            .any(|d| matches!(d, GraphType::Function(_)))
+            .then_some((self.offset, self.zoom, self.screen));
+        let key = (
+            self.revision,
+            self.graph_mode,
+            self.show,
+            self.slice,
+            self.view_x,
+            sampled,
+        );
+        if self.index.as_ref().is_none_or(|(k, _)| *k != key) {
+            self.index = Some((key, KdTree::new(self.samples())));
+        }
+        let r = 16.0 * (self.end - self.start) / (self.screen.x * self.zoom);
+        let Some((_, index)) = &self.index else {
+            return;
+        };
+        if let Some(sample) = index.nearest(self.to_coord(pos), r) {
+            let k = self.series[sample.series].color;
+            let color = if sample.alt {
+                self.alt_colors[k % self.alt_colors.len()]
+            } else {
+                self.main_colors[k % self.main_colors.len()]
+            };
+            let p = self.to_screen(sample.pos.x, sample.pos.y);
+            painter.circle_stroke(p, 6.0, Stroke::new(2.0, color));
+            painter.text(
+                p + Vec2::new(8.0, -8.0),
+                Align2::LEFT_BOTTOM,
+                format!(
+                    "{}[{}]\n{:.5}\n{:.5}",
+                    sample.series, sample.index, sample.x, sample.value
+                ),
+                self.font(self.fonts.readout_size),
+                self.text_color,
+            );
+        }
+    }
+    fn samples(&self) -> Vec<Sample> {
+        let mut samples = Vec::new();
+        let mut push = |series: usize, index: usize, x: f32, value: &Complex, flatten: bool| {
+            let (y, z) = value.to_options();
+            let mut add = |pos: Pos2, alt: bool| {
+                if pos.x.is_finite() && pos.y.is_finite() {
+                    samples.push(Sample {
+                        pos,
+                        series,
+                        index,
+                        x,
+                        value: *value,
+                        alt,
+                    })
+                }
+            };
+            if flatten {
+                if let (Some(y), Some(z)) = (y, z) {
+                    add(Pos2::new(y, z), false)
+                }
+            } else {
+                let show = self.series_show(series);
+                if let Some(y) = y.filter(|_| show.is_some_and(|s| s.real())) {
+                    add(Pos2::new(x, y), false)
+                }
+                if let Some(z) = z.filter(|_| show.is_some_and(|s| s.imag())) {
+                    add(Pos2::new(x, z), true)
+                }
+            }
+        };
+        let flatten = matches!(
+            self.graph_mode,
+            GraphMode::Flatten | GraphMode::SliceFlatten
+        );
+        for (k, data) in self.data.iter().enumerate() {
+            if self.series_show(k).is_none() {
+                continue;
+            }
+            let sampled;
+            let data = match data {
+                GraphType::Function(f) => {
+                    sampled = self.sample_1d(f);
+                    &sampled
+                }
+                GraphType::Function3D(f) => {
+                    sampled = self.sample_3d(f);
+                    &sampled
+                }
+                _ => data,
+            };
+            match data {
+                GraphType::Width(data, start, end) if data.len() >= 2 => {
+                    for (i, y) in data.iter().enumerate() {
+                        let x = (i as f32 / (data.len() - 1) as f32 - 0.5) * (end - start)
+                            + (start + end) / 2.0;
+                        push(k, i, x, y, self.graph_mode == GraphMode::Flatten)
+                    }
+                }
+                GraphType::Coord(data) => {
+                    for (i, (x, y)) in data.iter().enumerate() {
+                        push(k, i, *x, y, self.graph_mode == GraphMode::Flatten)
+                    }
+                }
+                GraphType::Width3D(data, start_x, _, end_x, _)
+                    if matches!(self.graph_mode, GraphMode::Slice | GraphMode::SliceFlatten) =>
+                {
+                    let len = data.len().isqrt();
+                    let slice = self.slice.min(len - 1);
+                    let x = |i: usize| {
+                        (i as f32 / (len - 1) as f32 - 0.5) * (end_x - start_x)
+                            + (start_x + end_x) / 2.0
+                    };
+                    if self.view_x {
+                        for (i, y) in data[slice * len..(slice + 1) * len].iter().enumerate() {
+                            push(k, slice * len + i, x(i), y, flatten)
+                        }
+                    } else {
+                        for (i, y) in data.iter().skip(slice).step_by(len).enumerate() {
+                            push(k, slice + i * len, x(i), y, flatten)
+                        }
+                    }
+                }
+                GraphType::Width(_, _, _)
+                | GraphType::Width3D(_, _, _, _, _)
+                | GraphType::Coord3D(_)
+                | GraphType::Function(_)
+                | GraphType::Function3D(_) => {}
+            }
+        }
+        samples
+    }
+    fn write_crosshair(&self, painter: &Painter) {
+        if !self.mouse_moved || self.disable_coord {
+            return;
+        }
+        let Some(pos) = self.mouse_position else {
+            return;
+        };
+        let stroke = Stroke::new(1.0, self.axis_color);
+        painter.vline(pos.x, Rangef::new(0.0, self.screen.y), stroke);
+        painter.hline(Rangef::new(0.0, self.screen.x), pos.y, stroke);
+        let p = self.to_coord(pos);
+        let prec = self.precision();
+        self.write_label(
+            painter,
+            Pos2::new(pos.x, self.screen.y),
+            Align2::CENTER_BOTTOM,
+            format!("{:.prec$}", p.x),
+        );
+        self.write_label(
+            painter,
+            Pos2::new(0.0, pos.y),
+            Align2::LEFT_CENTER,
+            format!("{:.prec$}", p.y),
+        );
+    }
+    fn write_measure(&self, painter: &Painter) {
+        let stroke = Stroke::new(2.0, self.axis_color);
+        let pts = self
+            .measure_points
+            .iter()
+            .map(|p| self.to_screen(p.x, p.y))
+            .collect::<Vec<Pos2>>();
+        for p in &pts {
+            painter.circle_stroke(*p, 5.0, stroke);
+        }
+        let (Some((a, b)), &[pa, pb]) = (self.get_measure(), &pts[..]) else {
+            return;
+        };
+        painter.line_segment([pa, pb], Stroke::new(1.0, self.axis_color));
+        let d = b - a;
+        let prec = self.precision();
+        let mut text = format!(
+            "dx {:.prec$}\ndy {:.prec$}\nslope {:.prec$}\ndist {:.prec$}",
+            d.x,
+            d.y,
+            d.y / d.x,
+            d.length()
+        );
+        if let Some(int) = self
+            .measure_series
+            .and_then(|h| self.series_index(h))
+            .and_then(|k| self.integrate(k, a.x, b.x))
+        {
+            text += &format!("\nint {int:.prec$}");
+        }
+        self.write_label(painter, pb + Vec2::new(8.0, 0.0), Align2::LEFT_TOP, text);
+    }
+    fn write_label(&self, painter: &Painter, pos: Pos2, align: Align2, text: String) {
+        let galley =
+            painter.layout_no_wrap(text, self.font(self.fonts.readout_size), self.text_color);
+        let rect = align.anchor_size(pos, galley.size());
+        painter.rect_filled(rect.expand(2.0), 0.0, self.background_color);
+        painter.galley(rect.min, galley, self.text_color);
+    }
+    fn precision(&self) -> usize {
+        let unit = (self.end - self.start) / (self.screen.x * self.zoom);
+        (-unit.log10()).ceil().max(0.0) as usize
+    }
     fn write_angle(&self, painter: &Painter) {
         if !self.disable_coord {
             painter.text(
@@ -338,7 +1842,7 @@ impl Graph {
                         .round()
                         .rem_euclid(360.0),
                 ),
-                FontId::monospace(16.0),
+                self.font(self.fonts.readout_size),
                 self.text_color,
             );
         }
@@ -359,36 +1863,157 @@ impl Graph {
     #[allow(clippy::too_many_arguments)]
     fn draw_point(
         &self,
-        painter: &Painter,
+        shapes: &mut Vec<Shape>,
         ui: &Ui,
         x: f32,
         y: f32,
         color: &Color32,
-        last: Option<Pos2>,
-    ) -> Option<Pos2> {
+        last: Option<(Pos2, f32)>,
+    ) -> Option<(Pos2, f32)> {
         if !x.is_finite() || !y.is_finite() {
             return None;
         }
-        let pos = self.to_screen(x, y);
+        self.draw_pos(shapes, ui, self.to_screen(x, y), color, last)
+    }
+    fn draw_pos(
+        &self,
+        shapes: &mut Vec<Shape>,
+        ui: &Ui,
+        pos: Pos2,
+        color: &Color32,
+        last: Option<(Pos2, f32)>,
+    ) -> Option<(Pos2, f32)> {
         if pos.x > -2.0
             && pos.x < self.screen.x + 2.0
             && pos.y > -2.0
             && pos.y < self.screen.y + 2.0
         {
             let rect = Rect::from_center_size(pos, Vec2::splat(3.0));
-            painter.rect_filled(rect, 0.0, *color);
+            shapes.push(Shape::rect_filled(rect, 0.0, *color));
         }
         if self.lines {
-            if let Some(last) = last {
-                if ui.is_rect_visible(Rect::from_points(&[last, pos])) {
-                    painter.line_segment([last, pos], Stroke::new(1.0, *color));
+            let mut jump = f32::INFINITY;
+            if let Some((last, prev)) = last {
+                let axis = self.to_screen(0.0, 0.0).y;
+                let j = (pos - last).length();
+                let sides = (last.y - axis, pos.y - axis);
+                if !self.is_discontinuous(j, prev, self.screen.y, sides) {
+                    jump = j;
+                    if ui.is_rect_visible(Rect::from_points(&[last, pos])) {
+                        shapes.push(Shape::line_segment([last, pos], Stroke::new(1.0, *color)));
+                    }
                 }
             }
-            Some(pos)
+            Some((pos, jump))
         } else {
             None
         }
     }
+    fn draw_decimated<'a>(
+        &self,
+        shapes: &mut Vec<Shape>,
+        ui: &Ui,
+        pts: impl Iterator<Item = (f32, &'a Complex)> + Clone,
+        k: usize,
+        show: Show,
+    ) {
+        for (imag, colors) in [(false, &self.main_colors), (true, &self.alt_colors)] {
+            if (!imag && !show.real()) || (imag && !show.imag()) {
+                continue;
+            }
+            let color = &colors[k % colors.len()];
+            let mut last: Option<(Pos2, f32)> = None;
+            let mut col = None;
+            let pts = pts.clone().map(|(x, y)| {
+                let (y, z) = y.to_options();
+                (x, if imag { z } else { y })
+            });
+            for (x, y) in self.decimate(pts) {
+                //the min/max stroke inside a column is not a discontinuity, only steps
+                //between columns are checked and compared against each other
+                let n = self.column(x);
+                let same = col == Some(n);
+                col = Some(n);
+                let prev = if same {
+                    last.map(|(p, _)| (p, f32::INFINITY))
+                } else {
+                    last
+                };
+                let next = y.and_then(|y| self.draw_point(shapes, ui, x, y, color, prev));
+                last = match next {
+                    Some((p, _)) if same => last.map(|(_, j)| (p, j)),
+                    _ => next,
+                };
+            }
+        }
+    }
+    //m4 decimation, keeps the first, min, max and last point of every pixel column,
+    //everything off screen is folded into the columns just outside it
+    fn decimate(&self, pts: impl Iterator<Item = (f32, Option<f32>)>) -> Vec<(f32, Option<f32>)> {
+        let mut out = Vec::new();
+        let mut col: Option<(i64, [(f32, f32); 4])> = None;
+        let flush = |out: &mut Vec<(f32, Option<f32>)>,
+                     [first, min, max, last]: [(f32, f32); 4]| {
+            let (lo, hi) = if min.0 <= max.0 {
+                (min, max)
+            } else {
+                (max, min)
+            };
+            for (x, y) in [first, lo, hi, last] {
+                if out.last() != Some(&(x, Some(y))) {
+                    out.push((x, Some(y)))
+                }
+            }
+        };
+        for (x, y) in pts {
+            match y {
+                Some(y) if x.is_finite() && y.is_finite() => {
+                    let n = self.column(x);
+                    match &mut col {
+                        Some((c, [_, min, max, last])) if *c == n => {
+                            if y < min.1 {
+                                *min = (x, y)
+                            }
+                            if y > max.1 {
+                                *max = (x, y)
+                            }
+                            *last = (x, y)
+                        }
+                        _ => {
+                            if let Some((_, c)) = col.take() {
+                                flush(&mut out, c)
+                            }
+                            col = Some((n, [(x, y); 4]))
+                        }
+                    }
+                }
+                _ => {
+                    if let Some((_, c)) = col.take() {
+                        flush(&mut out, c)
+                    }
+                    if out.last().is_none_or(|p| p.1.is_some()) {
+                        out.push((x, None))
+                    }
+                }
+            }
+        }
+        if let Some((_, c)) = col {
+            flush(&mut out, c)
+        }
+        out
+    }
+    fn column(&self, x: f32) -> i64 {
+        (self.to_screen(x, 0.0).x.floor() as i64).clamp(-1, self.screen.x as i64 + 1)
+    }
+    //a pole flips sign with the magnitude growing into it from both sides, so both ends
+    //must be further from the axis than the previous step, a root shrinks into the axis.
+    //only the previous step is known here so noisy data can trip it, hence off by default
+    fn is_discontinuous(&self, jump: f32, prev: f32, range: f32, (a, b): (f32, f32)) -> bool {
+        let flipped = a * b < 0.0 && a.abs().min(b.abs()) > prev;
+        self.discontinuity.is_some_and(|d| {
+            jump > d.range * range && (jump > d.neighbours * prev || (d.sign_flip && flipped))
+        })
+    }
     fn write_axis(&self, painter: &Painter) {
         let c = self.to_coord(Pos2::new(0.0, 0.0));
         let cf = self.to_coord(self.screen.to_pos2());
@@ -399,8 +2024,6 @@ impl Graph {
         };
         let stx = (c.x / r).round() * r;
         let sty = (c.y / r).round() * r;
-        let enx = (cf.x / r).round() * r;
-        let eny = (cf.y / r).round() * r;
         let s = if self.scale_axis {
             0
         } else {
@@ -526,19 +2149,131 @@ impl Graph {
             }
         }*/
     }
-    fn vec3_to_pos_depth(&self, p: Vec3) -> (Pos2, f32) {
-        let cos_phi = self.phi.cos();
-        let sin_phi = self.phi.sin();
-        let cos_theta = self.theta.cos();
-        let sin_theta = self.theta.sin();
-        let x1 = p.x * cos_phi + p.y * sin_phi;
-        let y1 = -p.x * sin_phi + p.y * cos_phi;
-        let z2 = -p.z * cos_theta - y1 * sin_theta;
-        let d = p.z * sin_theta - y1 * cos_theta;
-        (
-            Pos2::new(x1, z2) * self.delta / self.box_size + self.screen / 2.0,
-            d / ((self.end - self.start) * 3.0f32.sqrt()) + 0.5,
-        )
+    fn vec3_to_pos_depth(&self, p: Vec3) -> (Pos2, f32) {
+        let cos_phi = self.phi.cos();
+        let sin_phi = self.phi.sin();
+        let cos_theta = self.theta.cos();
+        let sin_theta = self.theta.sin();
+        let x1 = p.x * cos_phi + p.y * sin_phi;
+        let y1 = -p.x * sin_phi + p.y * cos_phi;
+        let z2 = -p.z * cos_theta - y1 * sin_theta;
+        let d = p.z * sin_theta - y1 * cos_theta;
+        (
+            Pos2::new(x1, z2) * self.perspective_scale(d) * self.delta / self.box_size
+                + self.screen / 2.0,
+            d / ((self.end - self.start) * 3.0f32.sqrt()) + 0.5,
+        )
+    }
+    fn camera(&self) -> f32 {
+        self.view_distance * (self.end - self.start) * 3.0f32.sqrt() / 2.0
+    }
+    //depth is held at the near plane so nothing at or behind the camera gets mirrored
+    fn perspective_scale(&self, d: f32) -> f32 {
+        if self.perspective {
+            let cam = self.camera();
+            cam / (cam - d).max(cam * NEAR) * (PI / 6.0).tan() / (self.fov / 2.0).tan()
+        } else {
+            1.0
+        }
+    }
+    fn culled(&self, depth: f32) -> bool {
+        self.perspective
+            && (depth - 0.5) * (self.end - self.start) * 3.0f32.sqrt()
+                >= self.camera() * (1.0 - NEAR)
+    }
+    fn pos_depth_to_vec3(&self, p: Pos2, d: f32) -> Vec3 {
+        let cos_phi = self.phi.cos();
+        let sin_phi = self.phi.sin();
+        let cos_theta = self.theta.cos();
+        let sin_theta = self.theta.sin();
+        let d = (d - 0.5) * (self.end - self.start) * 3.0f32.sqrt();
+        let p = (p - self.screen / 2.0) * self.box_size / (self.delta * self.perspective_scale(d));
+        let (x1, z2) = (p.x, p.y);
+        let z = -z2 * cos_theta + d * sin_theta;
+        let y1 = -z2 * sin_theta - d * cos_theta;
+        Vec3::new(x1 * cos_phi - y1 * sin_phi, x1 * sin_phi + y1 * cos_phi, z)
+    }
+    fn pick_3d(&self, pts: &[(f32, Prim, Color32)]) -> Option<(Pos2, Vec3)> {
+        if !self.mouse_moved {
+            return None;
+        }
+        let mouse = self.mouse_position?;
+        let mut best: Option<(Pos2, f32)> = None;
+        for (d, draw, _) in pts {
+            if let Prim::Point(p) = draw
+                && p.distance_sq(mouse) <= 64.0
+                && best.is_none_or(|(_, b)| *d > b)
+            {
+                best = Some((*p, *d))
+            }
+        }
+        best.map(|(p, d)| (p, self.pos_depth_to_vec3(p, d)))
+    }
+    fn draw_raster<'a>(
+        &mut self,
+        ctx: &Context,
+        pts: impl Iterator<Item = &'a (f32, Prim, Color32)>,
+    ) {
+        let ppp = self.pixels_per_point;
+        let (w, h) = (
+            (self.screen.x * ppp) as usize,
+            (self.screen.y * ppp) as usize,
+        );
+        let mut pixels = vec![Color32::TRANSPARENT; w * h];
+        let mut depth = vec![f32::NEG_INFINITY; w * h];
+        let mut plot = |p: Pos2, d: f32, c: Color32, r: isize| {
+            let (x, y) = (p.x as isize, p.y as isize);
+            for j in (y - r).max(0)..=(y + r).min(h as isize - 1) {
+                for i in (x - r).max(0)..=(x + r).min(w as isize - 1) {
+                    let k = j as usize * w + i as usize;
+                    if d >= depth[k] {
+                        depth[k] = d;
+                        pixels[k] = c;
+                    }
+                }
+            }
+        };
+        let radius = |t: f32| ((t * ppp - 1.0) / 2.0).round().max(0.0) as isize;
+        let max = (4 * (w + h)) as f32;
+        for (d, draw, c) in pts {
+            match *draw {
+                Prim::Line(a, b, t) => {
+                    if t <= 0.0 {
+                        continue;
+                    }
+                    let (pa, pb) = (a.0 * ppp, b.0 * ppp);
+                    let steps = (pb - pa).abs().max_elem().ceil().min(max).max(1.0);
+                    for i in 0..=steps as usize {
+                        let s = i as f32 / steps;
+                        plot(pa.lerp(pb, s), a.1 + (b.1 - a.1) * s, *c, radius(t));
+                    }
+                }
+                Prim::Point(a) => plot(a * ppp, *d, *c, radius(3.0)),
+            }
+        }
+        let image = ColorImage {
+            size: [w, h],
+            pixels,
+        };
+        if let Some(tex) = &mut self.raster {
+            tex.set(image, TextureOptions::NEAREST)
+        } else {
+            self.raster = Some(ctx.load_texture("raster", image, TextureOptions::NEAREST))
+        }
+    }
+    fn crosshair_3d(&self, v: Vec3) -> Vec<(f32, Prim, Color32)> {
+        let p = self.vec3_to_pos_depth(v);
+        [
+            Vec3::new(self.start, v.y, v.z),
+            Vec3::new(v.x, self.start, v.z),
+            Vec3::new(v.x, v.y, self.start),
+        ]
+        .into_iter()
+        .map(|a| {
+            let a = self.vec3_to_pos_depth(a);
+            ((a.1 + p.1) / 2.0, Prim::Line(a, p, 1.0), self.axis_color)
+        })
+        .collect()
     }
     /*fn vec3_to_pos(&self, p: Vec3) -> Pos2 {
         let cos_phi = self.phi.cos();
@@ -550,23 +2285,30 @@ impl Graph {
         let z2 = -p.z * cos_theta - y1 * sin_theta;
         Pos2::new(x1, z2) * self.delta / self.box_size + self.screen / 2.0
     }*/
-    #[allow(clippy::too_many_arguments)]
     fn draw_point_3d(
         &self,
         x: f32,
         y: f32,
         z: f32,
         color: &Color32,
-        a: Option<((Pos2, f32), Vec3, bool)>,
-        b: Option<((Pos2, f32), Vec3, bool)>,
-    ) -> (Option<((Pos2, f32), Vec3, bool)>, Vec<(f32, Draw, Color32)>) {
-        let mut draws = Vec::new();
+        a: Option<Node>,
+        b: Option<Node>,
+    ) -> (Option<Node>, DrawList) {
+        match self.project_3d(x, y, z, color) {
+            Some(p) => self.connect_3d(p, color, a, b),
+            None => (None, Vec::new()),
+        }
+    }
+    fn project_3d(&self, x: f32, y: f32, z: f32, color: &Color32) -> Option<Projected> {
         if !x.is_finite() || !y.is_finite() || !z.is_finite() {
-            return (None, draws);
+            return None;
         }
         let z = z + self.offset.z;
         let v = Vec3::new(x, y, z);
         let pos = self.vec3_to_pos_depth(v);
+        if self.culled(pos.1) {
+            return None;
+        }
         let inside = self.ignore_bounds
             || (x >= self.start
                 && x <= self.end
@@ -574,83 +2316,58 @@ impl Graph {
                 && y <= self.end
                 && z >= self.start
                 && z <= self.end);
-        if inside {
-            draws.push((pos.1, Draw::Point(pos.0), self.shift_hue(pos.1, color)));
+        let point = inside.then(|| (pos.1, Prim::Point(pos.0), self.shift_hue(pos.1, color)));
+        Some(((pos, v, inside, f32::INFINITY), point))
+    }
+    fn connect_3d(
+        &self,
+        ((pos, v, inside, _), point): Projected,
+        color: &Color32,
+        a: Option<Node>,
+        b: Option<Node>,
+    ) -> (Option<Node>, DrawList) {
+        let mut draws: DrawList = point.into_iter().collect();
+        if !self.lines {
+            return (None, draws);
         }
-        if self.lines {
-            let mut body = |last: ((Pos2, f32), Vec3, bool)| {
-                if inside && last.2 {
-                    let d = (pos.1 + last.0.1) / 2.0;
-                    draws.push((
-                        d,
-                        Draw::Line(last.0.0, pos.0, 1.0),
-                        self.shift_hue(d, color),
-                    ));
-                } else if inside {
-                    let mut vi = last.1;
-                    let xi = vi.x;
-                    if xi < self.start {
-                        vi = v + (vi - v) * ((self.start - x) / (xi - x));
-                    } else if xi > self.end {
-                        vi = v + (vi - v) * ((self.end - x) / (xi - x));
-                    }
-                    let yi = vi.y;
-                    if yi < self.start {
-                        vi = v + (vi - v) * ((self.start - y) / (yi - y));
-                    } else if yi > self.end {
-                        vi = v + (vi - v) * ((self.end - y) / (yi - y));
-                    }
-                    let zi = vi.z;
-                    if zi < self.start {
-                        vi = v + (vi - v) * ((self.start - z) / (zi - z));
-                    } else if zi > self.end {
-                        vi = v + (vi - v) * ((self.end - z) / (zi - z));
-                    }
-                    let last = self.vec3_to_pos_depth(vi);
-                    let d = (pos.1 + last.1) / 2.0;
-                    draws.push((d, Draw::Line(last.0, pos.0, 1.0), self.shift_hue(d, color)));
-                } else if last.2 {
-                    let mut vi = v;
-                    let v = last.1;
-                    let (x, y, z) = (v.x, v.y, v.z);
-                    let pos = self.vec3_to_pos_depth(v);
-                    let xi = vi.x;
-                    if xi < self.start {
-                        vi = v + (vi - v) * ((self.start - x) / (xi - x));
-                    } else if xi > self.end {
-                        vi = v + (vi - v) * ((self.end - x) / (xi - x));
-                    }
-                    let yi = vi.y;
-                    if yi < self.start {
-                        vi = v + (vi - v) * ((self.start - y) / (yi - y));
-                    } else if yi > self.end {
-                        vi = v + (vi - v) * ((self.end - y) / (yi - y));
-                    }
-                    let zi = vi.z;
-                    if zi < self.start {
-                        vi = v + (vi - v) * ((self.start - z) / (zi - z));
-                    } else if zi > self.end {
-                        vi = v + (vi - v) * ((self.end - z) / (zi - z));
-                    }
-                    let last = self.vec3_to_pos_depth(vi);
-                    let d = (pos.1 + last.1) / 2.0;
-                    draws.push((d, Draw::Line(last.0, pos.0, 1.0), self.shift_hue(d, color)));
-                } else {
-                }
-                //TODO deal with lines only intersecting
-            };
-            if let Some(last) = a {
-                body(last)
+        let z = v.z;
+        let mut jump = f32::INFINITY;
+        let mut body = |last: Node| {
+            let j = (z - last.1.z).abs();
+            let sides = (last.1.z - self.offset.z, z - self.offset.z);
+            if self.is_discontinuous(j, last.3, self.end - self.start, sides) {
+                return;
             }
-            if let Some(last) = b {
-                body(last)
+            jump = jump.min(j);
+            let ends = if self.ignore_bounds {
+                Some((last.1, v))
+            } else {
+                clip_segment(last.1, v, self.start, self.end)
+            };
+            if let Some((a, b)) = ends {
+                let a = if last.2 {
+                    last.0
+                } else {
+                    self.vec3_to_pos_depth(a)
+                };
+                let b = if inside {
+                    pos
+                } else {
+                    self.vec3_to_pos_depth(b)
+                };
+                let d = (a.1 + b.1) / 2.0;
+                draws.push((d, Prim::Line(a, b, 1.0), self.shift_hue(d, color)));
             }
-            (Some((pos, Vec3::new(x, y, z), inside)), draws)
-        } else {
-            (None, draws)
+        };
+        if let Some(last) = a {
+            body(last)
+        }
+        if let Some(last) = b {
+            body(last)
         }
+        (Some((pos, v, inside, jump)), draws)
     }
-    fn write_axis_3d(&self, painter: &Painter) -> Vec<(f32, Draw, Color32)> {
+    fn write_axis_3d(&self, painter: &Painter) -> Vec<(f32, Prim, Color32)> {
         let mut lines = Vec::new();
         if self.disable_axis {
             return lines;
@@ -710,11 +2427,7 @@ impl Graph {
                     } else {
                         1.0
                     },
-                    Draw::Line(
-                        vertices[*i].0,
-                        vertices[*j].0,
-                        vertices[*i].1 + vertices[*j].1,
-                    ),
+                    Prim::Line(vertices[*i], vertices[*j], vertices[*i].1 + vertices[*j].1),
                     self.axis_color,
                 ));
                 let p = vertices[*i].0 + vertices[*j].0.to_vec2();
@@ -735,12 +2448,16 @@ impl Graph {
                 painter.text(
                     p / 2.0,
                     align,
-                    if s == "z" {
-                        format!("z{}", " ".repeat(n.len()))
-                    } else {
-                        s.to_string()
+                    match s {
+                        "\nx" => format!("\n{}", self.label(0).as_deref().unwrap_or("x")),
+                        "\ny" => format!("\n{}", self.label(1).as_deref().unwrap_or("y")),
+                        _ => format!(
+                            "{}{}",
+                            self.label(2).as_deref().unwrap_or("z"),
+                            " ".repeat(n.len())
+                        ),
                     },
-                    FontId::monospace(16.0),
+                    self.font(self.fonts.label_size),
                     self.text_color,
                 );
                 for i in st..=e {
@@ -748,7 +2465,7 @@ impl Graph {
                         start + (i - st) as f32 * (end - start) / (e - st) as f32,
                         align,
                         i as f32 - if s == "z" { self.offset.z } else { 0.0 },
-                        FontId::monospace(16.0),
+                        self.font(self.fonts.tick_size),
                         self.text_color,
                     );
                 }
@@ -759,11 +2476,7 @@ impl Graph {
                     } else {
                         1.0
                     },
-                    Draw::Line(
-                        vertices[*i].0,
-                        vertices[*j].0,
-                        vertices[*i].1 + vertices[*j].1,
-                    ),
+                    Prim::Line(vertices[*i], vertices[*j], vertices[*i].1 + vertices[*j].1),
                     self.axis_color,
                 ));
             }
@@ -771,21 +2484,24 @@ impl Graph {
         lines
     }
     fn keybinds(&mut self, ui: &Ui) {
+        let before = self.get_view();
+        //laid out before taking the input lock, layout needs the context too
+        let legend = self.legend_rows(ui.painter());
+        let mut undone = false;
         ui.input(|i| {
             let multi = i.multi_touch();
             let interact = i.pointer.interact_pos();
             if i.pointer.primary_down()
                 && i.pointer.press_start_time().unwrap_or(0.0) < i.time
                 && multi.is_none()
+                && let (Some(interact), Some(last)) = (interact, self.last_interact)
             {
-                if let (Some(interact), Some(last)) = (interact, self.last_interact) {
-                    let delta = interact - last;
-                    if self.is_3d {
-                        self.phi = (self.phi - delta.x / 512.0).rem_euclid(TAU);
-                        self.theta = (self.theta + delta.y / 512.0).rem_euclid(TAU);
-                    } else {
-                        self.offset += delta / self.zoom;
-                    }
+                let delta = interact - last;
+                if self.is_3d {
+                    self.phi = (self.phi - delta.x / 512.0).rem_euclid(TAU);
+                    self.theta = (self.theta + delta.y / 512.0).rem_euclid(TAU);
+                } else {
+                    self.offset += delta / self.zoom;
                 }
             }
             self.last_interact = interact;
@@ -880,19 +2596,23 @@ impl Graph {
             }
             if i.key_pressed(Key::Z) {
                 self.disable_lines = !self.disable_lines;
+                self.revision += 1;
             }
             if i.key_pressed(Key::X) {
                 self.disable_axis = !self.disable_axis;
+                self.revision += 1;
             }
             if i.key_pressed(Key::C) {
                 self.disable_coord = !self.disable_coord;
             }
             if i.key_pressed(Key::V) {
                 self.scale_axis = !self.scale_axis; //TODO
+                self.revision += 1;
             }
             if i.key_pressed(Key::R) {
                 self.anti_alias = !self.anti_alias;
-                self.cache = None;
+                self.cache.clear();
+                self.revision += 1;
             }
             if self.is_3d {
                 if i.key_pressed(Key::F) {
@@ -903,9 +2623,11 @@ impl Graph {
                 }
                 if i.key_pressed(Key::P) {
                     self.ignore_bounds = !self.ignore_bounds;
+                    self.revision += 1;
                 }
                 if i.key_pressed(Key::O) {
                     self.color_depth = !self.color_depth;
+                    self.revision += 1;
                 }
                 let mut changed = false;
                 if i.key_pressed(Key::Semicolon) && self.box_size > 0.1 {
@@ -928,7 +2650,12 @@ impl Graph {
                     }
                 }
                 if i.key_pressed(Key::Y) {
-                    self.show_box = !self.show_box
+                    self.show_box = !self.show_box;
+                    self.revision += 1;
+                }
+                if i.key_pressed(Key::N) {
+                    self.perspective = !self.perspective;
+                    self.revision += 1;
                 }
                 self.phi = (self.phi - i.raw_scroll_delta.x / 512.0).rem_euclid(TAU);
                 self.theta = (self.theta + i.raw_scroll_delta.y / 512.0).rem_euclid(TAU);
@@ -986,11 +2713,13 @@ impl Graph {
                     self.slice = self.slice.saturating_sub(c)
                 }
                 if i.key_pressed(Key::Slash) {
-                    self.view_x = !self.view_x
+                    self.view_x = !self.view_x;
+                    self.revision += 1;
                 }
             }
             if i.key_pressed(Key::L) {
-                self.lines = !self.lines
+                self.lines = !self.lines;
+                self.revision += 1;
             }
             if self.is_complex && i.key_pressed(Key::I) {
                 self.show = match self.show {
@@ -1061,11 +2790,9 @@ impl Graph {
                     };
                 } else {
                     match self.graph_mode {
-                        GraphMode::Normal => {
-                            if self.is_3d {
-                                self.is_3d = false;
-                                self.graph_mode = GraphMode::Slice
-                            }
+                        GraphMode::Normal if self.is_3d => {
+                            self.is_3d = false;
+                            self.graph_mode = GraphMode::Slice
                         }
                         GraphMode::Slice => {
                             self.is_3d = true;
@@ -1075,6 +2802,57 @@ impl Graph {
                     }
                 }
             }
+            if i.key_pressed(Key::U) {
+                undone = if shift { self.redo() } else { self.undo() };
+            }
+            if i.key_pressed(Key::H) {
+                self.snap_coord = !self.snap_coord
+            }
+            if i.key_pressed(Key::K) {
+                self.crosshair = !self.crosshair
+            }
+            if i.key_pressed(Key::M) {
+                self.measure = !self.measure;
+                self.measure_points.clear();
+            }
+            for event in &i.events {
+                if let Event::Key {
+                    key,
+                    physical_key,
+                    pressed: true,
+                    repeat: false,
+                    modifiers,
+                    ..
+                } = event
+                {
+                    let key = physical_key.unwrap_or(*key);
+                    if let Some(n) = NUM_KEYS.iter().position(|k| *k == key) {
+                        self.toggle_part(n, modifiers.shift)
+                    }
+                }
+            }
+            let legend = if i.pointer.primary_clicked()
+                && let Some(pos) = i.pointer.interact_pos()
+            {
+                legend
+                    .iter()
+                    .find(|(rect, _, _, _)| rect.contains(pos))
+                    .map(|(_, n, imag, _)| (*n, *imag))
+            } else {
+                None
+            };
+            if let Some((n, imag)) = legend {
+                self.toggle_part(n, imag)
+            } else if self.measure
+                && !self.is_3d
+                && i.pointer.primary_clicked()
+                && let Some(pos) = i.pointer.interact_pos()
+            {
+                if self.measure_points.len() == 2 {
+                    self.measure_points.clear();
+                }
+                self.measure_points.push(self.to_coord(pos));
+            }
             if i.key_pressed(Key::T) {
                 self.offset = Vec3::splat(0.0);
                 self.zoom = 1.0;
@@ -1095,11 +2873,30 @@ impl Graph {
                 }
             }
         });
+        if !undone {
+            self.record_view(before);
+        }
     }
-    fn plot(&mut self, painter: &Painter, ui: &Ui) -> Vec<(f32, Draw, Color32)> {
+    fn plot(&mut self, shapes: &mut Vec<Shape>, ui: &Ui) -> Vec<(f32, Prim, Color32)> {
         let mut pts = Vec::new();
-        for (k, data) in self.data.iter().enumerate() {
+        for (n, data) in self.data.iter().enumerate() {
+            let Some(show) = self.series_show(n) else {
+                continue;
+            };
+            let k = self.series[n].color;
             let (mut a, mut b, mut c) = (None, None, None);
+            let sampled;
+            let data = match data {
+                GraphType::Function(f) => {
+                    sampled = self.sample_1d(f);
+                    &sampled
+                }
+                GraphType::Function3D(f) if self.graph_mode != GraphMode::DomainColoring => {
+                    sampled = self.sample_3d(f);
+                    &sampled
+                }
+                _ => data,
+            };
             match data {
                 GraphType::Width(data, start, end) => match self.graph_mode {
                     GraphMode::Normal
@@ -1107,38 +2904,46 @@ impl Graph {
                     | GraphMode::Slice
                     | GraphMode::SliceFlatten
                     | GraphMode::SliceDepth => {
-                        for (i, y) in data.iter().enumerate() {
-                            let x = (i as f32 / (data.len() - 1) as f32 - 0.5) * (end - start)
-                                + (start + end) / 2.0;
-                            let (y, z) = y.to_options();
-                            a = if !self.show.real() {
-                                None
-                            } else if let Some(y) = y {
-                                self.draw_point(
-                                    painter,
+                        let x = |i: usize| {
+                            (i as f32 / (data.len() - 1) as f32 - 0.5) * (end - start)
+                                + (start + end) / 2.0
+                        };
+                        if self.decimate.is_some_and(|n| data.len() > n) {
+                            let pts = data.iter().enumerate().map(|(i, y)| (x(i), y));
+                            self.draw_decimated(shapes, ui, pts, k, show);
+                            continue;
+                        }
+                        let pts = par_map(data.len(), |i| {
+                            let x = x(i);
+                            let (y, z) = data[i].to_options();
+                            let pos = |v: Option<f32>| {
+                                v.filter(|v| x.is_finite() && v.is_finite())
+                                    .map(|v| self.to_screen(x, v))
+                            };
+                            (
+                                pos(y.filter(|_| show.real())),
+                                pos(z.filter(|_| show.imag())),
+                            )
+                        });
+                        for (y, z) in pts {
+                            a = y.and_then(|p| {
+                                self.draw_pos(
+                                    shapes,
                                     ui,
-                                    x,
-                                    y,
+                                    p,
                                     &self.main_colors[k % self.main_colors.len()],
                                     a,
                                 )
-                            } else {
-                                None
-                            };
-                            b = if !self.show.imag() {
-                                None
-                            } else if let Some(z) = z {
-                                self.draw_point(
-                                    painter,
+                            });
+                            b = z.and_then(|p| {
+                                self.draw_pos(
+                                    shapes,
                                     ui,
-                                    x,
-                                    z,
+                                    p,
                                     &self.alt_colors[k % self.alt_colors.len()],
                                     b,
                                 )
-                            } else {
-                                None
-                            };
+                            });
                         }
                     }
                     GraphMode::Flatten => {
@@ -1146,7 +2951,7 @@ impl Graph {
                             let (y, z) = y.to_options();
                             a = if let (Some(y), Some(z)) = (y, z) {
                                 self.draw_point(
-                                    painter,
+                                    shapes,
                                     ui,
                                     y,
                                     z,
@@ -1159,19 +2964,16 @@ impl Graph {
                         }
                     }
                     GraphMode::Depth => {
-                        for (i, y) in data.iter().enumerate() {
-                            let (y, z) = y.to_options();
-                            c = if let (Some(x), Some(y)) = (y, z) {
-                                let z = (i as f32 / (data.len() - 1) as f32 - 0.5) * (end - start)
-                                    + (start + end) / 2.0;
-                                let (c, d) = self.draw_point_3d(
-                                    x,
-                                    y,
-                                    z,
-                                    &self.main_colors[k % self.main_colors.len()],
-                                    c,
-                                    None,
-                                );
+                        let color = &self.main_colors[k % self.main_colors.len()];
+                        let proj = par_map(data.len(), |i| {
+                            let (x, y) = data[i].to_options();
+                            let z = (i as f32 / (data.len() - 1) as f32 - 0.5) * (end - start)
+                                + (start + end) / 2.0;
+                            self.project_3d(x?, y?, z, color)
+                        });
+                        for p in proj {
+                            c = if let Some(p) = p {
+                                let (c, d) = self.connect_3d(p, color, c, None);
                                 pts.extend(d);
                                 c
                             } else {
@@ -1186,13 +2988,25 @@ impl Graph {
                     | GraphMode::Slice
                     | GraphMode::SliceFlatten
                     | GraphMode::SliceDepth => {
+                        if self.decimate.is_some_and(|n| data.len() > n)
+                            && data.is_sorted_by(|a, b| a.0 <= b.0)
+                        {
+                            self.draw_decimated(
+                                shapes,
+                                ui,
+                                data.iter().map(|(x, y)| (*x, y)),
+                                k,
+                                show,
+                            );
+                            continue;
+                        }
                         for (x, y) in data {
                             let (y, z) = y.to_options();
-                            a = if !self.show.real() {
+                            a = if !show.real() {
                                 None
                             } else if let Some(y) = y {
                                 self.draw_point(
-                                    painter,
+                                    shapes,
                                     ui,
                                     *x,
                                     y,
@@ -1202,11 +3016,11 @@ impl Graph {
                             } else {
                                 None
                             };
-                            b = if !self.show.imag() {
+                            b = if !show.imag() {
                                 None
                             } else if let Some(z) = z {
                                 self.draw_point(
-                                    painter,
+                                    shapes,
                                     ui,
                                     *x,
                                     z,
@@ -1223,7 +3037,7 @@ impl Graph {
                             let (y, z) = y.to_options();
                             a = if let (Some(y), Some(z)) = (y, z) {
                                 self.draw_point(
-                                    painter,
+                                    shapes,
                                     ui,
                                     y,
                                     z,
@@ -1258,27 +3072,34 @@ impl Graph {
                 GraphType::Width3D(data, start_x, start_y, end_x, end_y) => match self.graph_mode {
                     GraphMode::Flatten | GraphMode::Depth | GraphMode::Normal => {
                         let len = data.len().isqrt();
-                        let mut last = Vec::new();
-                        let mut cur = Vec::new();
-                        let mut lasti = Vec::new();
-                        let mut curi = Vec::new();
-                        for (i, z) in data.iter().enumerate() {
-                            let (i, j) = (i % len, i / len);
+                        let color = &self.main_colors[k % self.main_colors.len()];
+                        let alt = &self.alt_colors[k % self.alt_colors.len()];
+                        let proj = par_map(data.len(), |n| {
+                            let (i, j) = (n % len, n / len);
                             let x = (i as f32 / (len - 1) as f32 - 0.5) * (end_x - start_x)
                                 + (start_x + end_x) / 2.0;
                             let y = (j as f32 / (len - 1) as f32 - 0.5) * (end_y - start_y)
                                 + (start_y + end_y) / 2.0;
-                            let (z, w) = z.to_options();
-                            let p = if !self.show.real() {
-                                None
-                            } else if let Some(z) = z {
-                                let (c, d) = self.draw_point_3d(
-                                    x,
-                                    y,
+                            let (z, w) = data[n].to_options();
+                            (
+                                z.filter(|_| show.real())
+                                    .and_then(|z| self.project_3d(x, y, z, color)),
+                                w.filter(|_| show.imag())
+                                    .and_then(|w| self.project_3d(x, y, w, alt)),
+                            )
+                        });
+                        let mut last = Vec::new();
+                        let mut cur = Vec::new();
+                        let mut lasti = Vec::new();
+                        let mut curi = Vec::new();
+                        for (n, (z, w)) in proj.into_iter().enumerate() {
+                            let i = n % len;
+                            let p = if let Some(z) = z {
+                                let (c, d) = self.connect_3d(
                                     z,
-                                    &self.main_colors[k % self.main_colors.len()],
+                                    color,
                                     if i == 0 { None } else { cur[i - 1] },
-                                    if j == 0 { None } else { last[i] },
+                                    if last.is_empty() { None } else { last[i] },
                                 );
                                 pts.extend(d);
                                 c
@@ -1289,16 +3110,12 @@ impl Graph {
                             if i == len - 1 {
                                 last = std::mem::take(&mut cur);
                             }
-                            let p = if !self.show.imag() {
-                                None
-                            } else if let Some(w) = w {
-                                let (c, d) = self.draw_point_3d(
-                                    x,
-                                    y,
+                            let p = if let Some(w) = w {
+                                let (c, d) = self.connect_3d(
                                     w,
-                                    &self.alt_colors[k % self.alt_colors.len()],
+                                    alt,
                                     if i == 0 { None } else { curi[i - 1] },
-                                    if j == 0 { None } else { lasti[i] },
+                                    if lasti.is_empty() { None } else { lasti[i] },
                                 );
                                 pts.extend(d);
                                 c
@@ -1318,11 +3135,11 @@ impl Graph {
                             let x = (i as f32 / (len - 1) as f32 - 0.5) * (end_x - start_x)
                                 + (start_x + end_x) / 2.0;
                             let (y, z) = y.to_options();
-                            a = if !self.show.real() {
+                            a = if !show.real() {
                                 None
                             } else if let Some(y) = y {
                                 self.draw_point(
-                                    painter,
+                                    shapes,
                                     ui,
                                     x,
                                     y,
@@ -1332,11 +3149,11 @@ impl Graph {
                             } else {
                                 None
                             };
-                            b = if !self.show.imag() {
+                            b = if !show.imag() {
                                 None
                             } else if let Some(z) = z {
                                 self.draw_point(
-                                    painter,
+                                    shapes,
                                     ui,
                                     x,
                                     z,
@@ -1367,7 +3184,7 @@ impl Graph {
                             let (y, z) = y.to_options();
                             a = if let (Some(y), Some(z)) = (y, z) {
                                 self.draw_point(
-                                    painter,
+                                    shapes,
                                     ui,
                                     y,
                                     z,
@@ -1425,25 +3242,14 @@ impl Graph {
                     }
                     GraphMode::DomainColoring => {
                         let len = data.len().isqrt();
-                        let tex = if let Some(tex) = &self.cache {
-                            tex
-                        } else {
-                            let mut rgb = Vec::new();
-                            for z in data {
-                                rgb.extend(self.get_color(z));
-                            }
-                            let tex = ui.ctx().load_texture(
-                                "dc",
-                                ColorImage::from_rgb([len, len], &rgb),
-                                if self.anti_alias {
-                                    TextureOptions::LINEAR
-                                } else {
-                                    TextureOptions::NEAREST
-                                },
-                            );
-                            self.cache = Some(tex);
-                            self.cache.as_ref().unwrap()
-                        };
+                        let handle = self.series[n].handle;
+                        if !self.cache.contains_key(&handle) {
+                            let rgb = par_map(data.len(), |i| self.get_color(&data[i]));
+                            let image = ColorImage::from_rgb([len, len], rgb.as_flattened());
+                            let options = self.dc_options();
+                            set_dc(&mut self.cache, ui, handle, None, image, options);
+                        }
+                        let tex = &self.cache[&handle].1;
                         let a = (Pos2::new(*start_x, *start_y) * self.screen.x
                             / (self.end - self.start)
                             + self.screen_offset
@@ -1454,14 +3260,42 @@ impl Graph {
                             + self.screen_offset
                             + self.offset.get_2d())
                             * self.zoom;
-                        painter.image(
+                        shapes.push(Shape::image(
                             tex.id(),
                             Rect::from_points(&[a, b]),
                             Rect::from_min_max(Pos2::new(0.0, 0.0), Pos2::new(1.0, 1.0)),
                             Color32::WHITE,
-                        );
+                        ));
                     }
                 },
+                GraphType::Function(_) => unreachable!(),
+                GraphType::Function3D(f) => {
+                    let handle = self.series[n].handle;
+                    let view = Some((self.offset, self.zoom, self.screen, self.pixels_per_point));
+                    if self.cache.get(&handle).is_none_or(|(v, _)| *v != view) {
+                        let ppp = self.pixels_per_point;
+                        let (w, h) = (
+                            (self.screen.x * ppp) as usize,
+                            (self.screen.y * ppp) as usize,
+                        );
+                        let rgb = par_map(w * h, |n| {
+                            let (i, j) = (n % w, n / w);
+                            let p = Pos2::new(i as f32 + 0.5, j as f32 + 0.5) / ppp;
+                            let p = self.to_coord(p);
+                            self.get_color(&f(p.x, p.y))
+                        });
+                        let image = ColorImage::from_rgb([w, h], rgb.as_flattened());
+                        let options = self.dc_options();
+                        set_dc(&mut self.cache, ui, handle, view, image, options);
+                    }
+                    let tex = &self.cache[&handle].1;
+                    shapes.push(Shape::image(
+                        tex.id(),
+                        Rect::from_min_size(Pos2::new(0.0, 0.0), self.screen),
+                        Rect::from_min_max(Pos2::new(0.0, 0.0), Pos2::new(1.0, 1.0)),
+                        Color32::WHITE,
+                    ));
+                }
                 GraphType::Coord3D(data) => match self.graph_mode {
                     GraphMode::Slice
                     | GraphMode::SliceFlatten
@@ -1470,37 +3304,30 @@ impl Graph {
                     | GraphMode::Flatten
                     | GraphMode::Depth
                     | GraphMode::Normal => {
+                        let color = &self.main_colors[k % self.main_colors.len()];
+                        let alt = &self.alt_colors[k % self.alt_colors.len()];
+                        let proj = par_map(data.len(), |n| {
+                            let (x, y, z) = &data[n];
+                            let (z, w) = z.to_options();
+                            (
+                                z.filter(|_| show.real())
+                                    .and_then(|z| self.project_3d(*x, *y, z, color)),
+                                w.filter(|_| show.imag())
+                                    .and_then(|w| self.project_3d(*x, *y, w, alt)),
+                            )
+                        });
                         let mut last = None;
                         let mut lasti = None;
-                        for (x, y, z) in data {
-                            let (z, w) = z.to_options();
-                            last = if !self.show.real() {
-                                None
-                            } else if let Some(z) = z {
-                                let (c, d) = self.draw_point_3d(
-                                    *x,
-                                    *y,
-                                    z,
-                                    &self.main_colors[k % self.main_colors.len()],
-                                    last,
-                                    None,
-                                );
+                        for (z, w) in proj {
+                            last = if let Some(z) = z {
+                                let (c, d) = self.connect_3d(z, color, last, None);
                                 pts.extend(d);
                                 c
                             } else {
                                 None
                             };
-                            lasti = if !self.show.imag() {
-                                None
-                            } else if let Some(w) = w {
-                                let (c, d) = self.draw_point_3d(
-                                    *x,
-                                    *y,
-                                    w,
-                                    &self.alt_colors[k % self.alt_colors.len()],
-                                    lasti,
-                                    None,
-                                );
+                            lasti = if let Some(w) = w {
+                                let (c, d) = self.connect_3d(w, alt, lasti, None);
                                 pts.extend(d);
                                 c
                             } else {
@@ -1513,18 +3340,85 @@ impl Graph {
         }
         pts
     }
-    fn get_color(&self, z: &Complex) -> [u8; 3] {
-        let (x, y) = z.to_options();
-        let (x, y) = (x.unwrap_or(0.0), y.unwrap_or(0.0));
-        let abs = x.hypot(y);
-        let hue = 3.0 * (1.0 - y.atan2(x) / PI);
-        let sat = (1.0 + abs.fract()) / 2.0;
-        let val = {
-            let t1 = (x * PI).sin();
-            let t2 = (y * PI).sin();
-            (t1 * t2).abs().powf(0.125)
+    fn sample_1d(&self, f: &Arc<dyn Fn(f32) -> Complex + Send + Sync>) -> GraphType {
+        let (a, b) = if self.screen.x > 0.0 {
+            (
+                self.to_coord(Pos2::new(0.0, 0.0)).x,
+                self.to_coord(self.screen.to_pos2()).x,
+            )
+        } else {
+            (self.start, self.end)
+        };
+        let scale = self.screen.x * self.zoom / (self.end - self.start);
+        let n = (self.screen.x / 8.0).max(16.0) as usize;
+        let mut data = Vec::new();
+        let mut last = (a, f(a));
+        data.push(last);
+        for i in 1..=n {
+            let x = a + (b - a) * i as f32 / n as f32;
+            let next = (x, f(x));
+            self.refine(f, last, next, 8, scale, &mut data);
+            data.push(next);
+            last = next;
+        }
+        GraphType::Coord(data)
+    }
+    fn refine(
+        &self,
+        f: &Arc<dyn Fn(f32) -> Complex + Send + Sync>,
+        (x0, v0): (f32, Complex),
+        (x1, v1): (f32, Complex),
+        depth: usize,
+        scale: f32,
+        data: &mut Vec<(f32, Complex)>,
+    ) {
+        let xm = (x0 + x1) / 2.0;
+        let vm = f(xm);
+        let (a, b, m) = (v0.to_options(), v1.to_options(), vm.to_options());
+        let mut dev = 0.0f32;
+        let mut jump = 0.0f32;
+        for (a, b, m) in [(a.0, b.0, m.0), (a.1, b.1, m.1)] {
+            if let (Some(a), Some(b), Some(m)) = (a, b, m) {
+                if a.is_finite() && b.is_finite() && m.is_finite() {
+                    dev = dev.max((m - (a + b) / 2.0).abs() * scale);
+                    jump = jump.max((b - a).abs() * scale);
+                } else if a.is_finite() || b.is_finite() || m.is_finite() {
+                    dev = f32::INFINITY;
+                    jump = f32::INFINITY;
+                }
+            }
+        }
+        if dev > 0.5 && depth > 0 {
+            self.refine(f, (x0, v0), (xm, vm), depth - 1, scale, data);
+            data.push((xm, vm));
+            self.refine(f, (xm, vm), (x1, v1), depth - 1, scale, data);
+        } else if dev > 0.5 && jump > self.screen.y / 8.0 {
+            data.push((xm, Complex::from(None, None)));
+        }
+    }
+    fn sample_3d(&self, f: &Arc<dyn Fn(f32, f32) -> Complex + Send + Sync>) -> GraphType {
+        let n = self.resolution;
+        let pos = |i: usize| {
+            (i as f32 / (n - 1) as f32 - 0.5) * (self.end - self.start)
+                + (self.start + self.end) / 2.0
         };
-        hsv2rgb(hue, sat, val)
+        let mut data = Vec::with_capacity(n * n);
+        for j in 0..n {
+            for i in 0..n {
+                data.push(f(pos(i), pos(j)))
+            }
+        }
+        GraphType::Width3D(data, self.start, self.start, self.end, self.end)
+    }
+    fn dc_options(&self) -> TextureOptions {
+        if self.anti_alias {
+            TextureOptions::LINEAR
+        } else {
+            TextureOptions::NEAREST
+        }
+    }
+    fn get_color(&self, z: &Complex) -> [u8; 3] {
+        self.color_scheme.color(*z)
     }
     fn shift_hue(&self, diff: f32, color: &Color32) -> Color32 {
         if self.color_depth {
@@ -1534,86 +3428,586 @@ impl Graph {
         }
     }
 }
-fn hsv2rgb(hue: f32, sat: f32, val: f32) -> [u8; 3] {
-    if sat == 0.0 {
-        return rgb2val(val, val, val);
-    }
-    let i = hue.floor();
-    let f = hue - i;
-    let p = val * (1.0 - sat);
-    let q = val * (1.0 - sat * f);
-    let t = val * (1.0 - sat * (1.0 - f));
-    match i as usize % 6 {
-        0 => rgb2val(val, t, p),
-        1 => rgb2val(q, val, p),
-        2 => rgb2val(p, val, t),
-        3 => rgb2val(p, q, val),
-        4 => rgb2val(t, p, val),
-        _ => rgb2val(val, p, q),
+#[cfg(feature = "rayon")]
+fn par_map<T: Send>(len: usize, f: impl Fn(usize) -> T + Send + Sync) -> Vec<T> {
+    use rayon::prelude::*;
+    (0..len).into_par_iter().map(f).collect()
+}
+#[cfg(not(feature = "rayon"))]
+fn par_map<T>(len: usize, f: impl Fn(usize) -> T) -> Vec<T> {
+    (0..len).map(f).collect()
+}
+//reuses the series texture when it has one so panning does not allocate a new one
+fn set_dc(
+    cache: &mut HashMap<SeriesHandle, DcCache>,
+    ui: &Ui,
+    handle: SeriesHandle,
+    view: Option<CacheView>,
+    image: ColorImage,
+    options: TextureOptions,
+) {
+    match cache.entry(handle) {
+        Entry::Occupied(mut entry) => {
+            let (v, tex) = entry.get_mut();
+            tex.set(image, options);
+            *v = view
+        }
+        Entry::Vacant(entry) => {
+            entry.insert((view, ui.ctx().load_texture("dc", image, options)));
+        }
     }
 }
-fn rgb2val(r: f32, g: f32, b: f32) -> [u8; 3] {
-    [(255.0 * r) as u8, (255.0 * g) as u8, (255.0 * b) as u8]
+fn draw_3d(painter: &Painter, (_, draw, c): &(f32, Prim, Color32)) {
+    match *draw {
+        Prim::Line(a, b, t) => {
+            painter.line_segment([a.0, b.0], Stroke::new(t, *c));
+        }
+        Prim::Point(a) => {
+            let rect = Rect::from_center_size(a, Vec2::splat(3.0));
+            painter.rect_filled(rect, 0.0, *c);
+        }
+    }
 }
-pub fn get_lch(color: [f32; 3]) -> (f32, f32, f32) {
-    let c = (color[1].powi(2) + color[2].powi(2)).sqrt();
-    let h = color[2].atan2(color[1]);
-    (color[0], c, h)
+fn clip_segment(a: Vec3, b: Vec3, min: f32, max: f32) -> Option<(Vec3, Vec3)> {
+    let d = b - a;
+    let (mut t0, mut t1) = (0.0f32, 1.0f32);
+    for (p, dp) in [(a.x, d.x), (a.y, d.y), (a.z, d.z)] {
+        for (num, den) in [(p - min, -dp), (max - p, dp)] {
+            if den == 0.0 {
+                if num < 0.0 {
+                    return None;
+                }
+            } else if den < 0.0 {
+                t0 = t0.max(num / den)
+            } else {
+                t1 = t1.min(num / den)
+            }
+        }
+    }
+    (t0 <= t1).then(|| (a + d * t0, a + d * t1))
 }
-pub fn rgb_to_oklch(color: &mut [f32; 3]) {
-    let mut l = 0.4122214694707629 * color[0]
-        + 0.5363325372617349 * color[1]
-        + 0.0514459932675022 * color[2];
-    let mut m = 0.2119034958178251 * color[0]
-        + 0.6806995506452344 * color[1]
-        + 0.1073969535369405 * color[2];
-    let mut s = 0.0883024591900564 * color[0]
-        + 0.2817188391361215 * color[1]
-        + 0.6299787016738222 * color[2];
-
-    l = l.cbrt();
-    m = m.cbrt();
-    s = s.cbrt();
-
-    color[0] = 0.210454268309314 * l + 0.7936177747023054 * m - 0.0040720430116193 * s;
-    color[1] = 1.9779985324311684 * l - 2.42859224204858 * m + 0.450593709617411 * s;
-    color[2] = 0.0259040424655478 * l + 0.7827717124575296 * m - 0.8086757549230774 * s;
+struct Sample {
+    pos: Pos2,
+    series: usize,
+    index: usize,
+    x: f32,
+    value: Complex,
+    alt: bool,
 }
-fn oklch_to_rgb(color: &mut [f32; 3]) {
-    let mut l = color[0] + 0.3963377773761749 * color[1] + 0.2158037573099136 * color[2];
-    let mut m = color[0] - 0.1055613458156586 * color[1] - 0.0638541728258133 * color[2];
-    let mut s = color[0] - 0.0894841775298119 * color[1] - 1.2914855480194092 * color[2];
-
-    l = l.powi(3);
-    m = m.powi(3);
-    s = s.powi(3);
-
-    color[0] = 4.07674163607596 * l - 3.3077115392580635 * m + 0.2309699031821046 * s;
-    color[1] = -1.2684379732850317 * l + 2.6097573492876887 * m - 0.3413193760026572 * s;
-    color[2] = -0.0041960761386754 * l - 0.7034186179359363 * m + 1.7076146940746116 * s;
+struct KdTree {
+    points: Vec<Sample>,
 }
-fn shift_hue_by(color: &mut [f32; 3], diff: f32) {
-    let diff = TAU * diff;
-    let (_, c, hue) = get_lch(*color);
-    let mut new_hue = (hue + diff) % TAU;
-    if new_hue.is_sign_negative() {
-        new_hue += TAU;
-    }
-    color[1] = c * new_hue.cos();
-    color[2] = c * new_hue.sin();
+impl KdTree {
+    fn new(mut points: Vec<Sample>) -> Self {
+        Self::build(&mut points, false);
+        Self { points }
+    }
+    fn axis(p: Pos2, y: bool) -> f32 {
+        if y { p.y } else { p.x }
+    }
+    fn build(points: &mut [Sample], y: bool) {
+        if points.len() <= 1 {
+            return;
+        }
+        let mid = points.len() / 2;
+        points.select_nth_unstable_by(mid, |a, b| {
+            Self::axis(a.pos, y).total_cmp(&Self::axis(b.pos, y))
+        });
+        let (left, right) = points.split_at_mut(mid);
+        Self::build(left, !y);
+        Self::build(&mut right[1..], !y);
+    }
+    fn nearest(&self, p: Pos2, r: f32) -> Option<&Sample> {
+        let mut best = None;
+        let mut dist = r * r;
+        Self::search(&self.points, p, false, &mut best, &mut dist);
+        best
+    }
+    fn search<'a>(
+        points: &'a [Sample],
+        p: Pos2,
+        y: bool,
+        best: &mut Option<&'a Sample>,
+        dist: &mut f32,
+    ) {
+        if points.is_empty() {
+            return;
+        }
+        let mid = points.len() / 2;
+        let sample = &points[mid];
+        let d = sample.pos.distance_sq(p);
+        if d <= *dist {
+            *dist = d;
+            *best = Some(sample);
+        }
+        let diff = Self::axis(p, y) - Self::axis(sample.pos, y);
+        let (near, far) = if diff < 0.0 {
+            (&points[..mid], &points[mid + 1..])
+        } else {
+            (&points[mid + 1..], &points[..mid])
+        };
+        Self::search(near, p, !y, best, dist);
+        if diff * diff <= *dist {
+            Self::search(far, p, !y, best, dist);
+        }
+    }
 }
-fn shift_hue(diff: f32, color: &Color32) -> Color32 {
-    let mut color = [
-        color.r() as f32 / 255.0,
-        color.g() as f32 / 255.0,
-        color.b() as f32 / 255.0,
-    ];
-    rgb_to_oklch(&mut color);
-    shift_hue_by(&mut color, diff);
-    oklch_to_rgb(&mut color);
-    Color32::from_rgb(
-        (color[0] * 255.0) as u8,
-        (color[1] * 255.0) as u8,
-        (color[2] * 255.0) as u8,
-    )
+#[cfg(test)]
+mod tests {
+    use super::*;
+    fn close(a: Vec3, b: Vec3) -> bool {
+        (a - b).x.abs() < 1e-5 && (a - b).y.abs() < 1e-5 && (a - b).z.abs() < 1e-5
+    }
+    fn clip(a: [f32; 3], b: [f32; 3]) -> Option<(Vec3, Vec3)> {
+        clip_segment(
+            Vec3::new(a[0], a[1], a[2]),
+            Vec3::new(b[0], b[1], b[2]),
+            -1.0,
+            1.0,
+        )
+    }
+    #[test]
+    fn clip_inside() {
+        let (a, b) = clip([-0.5, 0.0, 0.5], [0.5, 0.25, -0.5]).unwrap();
+        assert!(close(a, Vec3::new(-0.5, 0.0, 0.5)));
+        assert!(close(b, Vec3::new(0.5, 0.25, -0.5)));
+    }
+    #[test]
+    fn clip_outside() {
+        assert!(clip([2.0, 0.0, 0.0], [3.0, 0.5, 0.0]).is_none());
+        assert!(clip([2.0, 2.0, 0.0], [-2.0, 2.0, 0.0]).is_none());
+        assert!(clip([0.5, 2.5, 0.0], [2.5, 0.5, 0.0]).is_none());
+    }
+    #[test]
+    fn clip_one_end_outside() {
+        let (a, b) = clip([0.0, 0.0, 0.0], [0.0, 0.0, 3.0]).unwrap();
+        assert!(close(a, Vec3::new(0.0, 0.0, 0.0)));
+        assert!(close(b, Vec3::new(0.0, 0.0, 1.0)));
+        let (a, b) = clip([-3.0, 0.0, 0.0], [0.0, 0.0, 0.0]).unwrap();
+        assert!(close(a, Vec3::new(-1.0, 0.0, 0.0)));
+        assert!(close(b, Vec3::new(0.0, 0.0, 0.0)));
+    }
+    #[test]
+    fn clip_both_ends_outside() {
+        let (a, b) = clip([-2.0, 0.0, 0.0], [2.0, 0.0, 0.0]).unwrap();
+        assert!(close(a, Vec3::new(-1.0, 0.0, 0.0)));
+        assert!(close(b, Vec3::new(1.0, 0.0, 0.0)));
+        let (a, b) = clip([-2.0, -2.0, -2.0], [2.0, 2.0, 2.0]).unwrap();
+        assert!(close(a, Vec3::new(-1.0, -1.0, -1.0)));
+        assert!(close(b, Vec3::new(1.0, 1.0, 1.0)));
+        let (a, b) = clip([-1.5, 0.0, -3.0], [1.5, 0.0, 3.0]).unwrap();
+        assert!(close(a, Vec3::new(-0.5, 0.0, -1.0)));
+        assert!(close(b, Vec3::new(0.5, 0.0, 1.0)));
+    }
+    #[test]
+    fn clip_touching() {
+        let (a, b) = clip([1.0, -2.0, 0.0], [1.0, 2.0, 0.0]).unwrap();
+        assert!(close(a, Vec3::new(1.0, -1.0, 0.0)));
+        assert!(close(b, Vec3::new(1.0, 1.0, 0.0)));
+        let (a, b) = clip([0.0, 2.0, 2.0], [2.0, 0.0, 0.0]).unwrap();
+        assert!(close(a, Vec3::new(1.0, 1.0, 1.0)));
+        assert!(close(b, Vec3::new(1.0, 1.0, 1.0)));
+    }
+    #[test]
+    fn clip_degenerate() {
+        let (a, b) = clip([0.5, 0.5, 0.5], [0.5, 0.5, 0.5]).unwrap();
+        assert!(close(a, b));
+        assert!(clip([1.5, 0.5, 0.5], [1.5, 0.5, 0.5]).is_none());
+    }
+    fn surface() -> GraphType {
+        GraphType::Width3D(vec![Complex::Real(0.0); 4], -1.0, -1.0, 1.0, 1.0)
+    }
+    #[test]
+    fn visibility_keeps_mode() {
+        let mut graph = Graph::new(vec![surface()], true, -1.0, 1.0);
+        graph.set_mode(GraphMode::DomainColoring);
+        let handle = graph.get_handles().next().unwrap();
+        assert!(graph.set_visible(handle, false));
+        assert!(graph.set_visible(handle, true));
+        assert!(!graph.is_3d);
+        graph.insert_series(surface());
+        assert!(!graph.is_3d);
+        assert!(graph.graph_mode == GraphMode::DomainColoring);
+    }
+    #[test]
+    fn undo_redo() {
+        let mut graph = Graph::new(Vec::new(), false, -1.0, 1.0);
+        assert!(!graph.undo());
+        graph.push_view();
+        graph.zoom = 2.0;
+        graph.push_view();
+        graph.zoom = 4.0;
+        assert!(graph.undo());
+        assert_eq!(graph.zoom, 2.0);
+        assert!(graph.undo());
+        assert_eq!(graph.zoom, 1.0);
+        assert!(!graph.undo());
+        assert!(graph.redo());
+        assert!(graph.redo());
+        assert_eq!(graph.zoom, 4.0);
+        assert!(!graph.redo());
+        graph.undo();
+        graph.push_view();
+        assert!(!graph.redo());
+    }
+    #[test]
+    fn history_is_capped() {
+        let mut graph = Graph::new(Vec::new(), false, -1.0, 1.0);
+        for i in 0..HISTORY * 2 {
+            graph.zoom = i as f32;
+            graph.push_view();
+        }
+        assert_eq!(graph.history.len(), HISTORY);
+        assert_eq!(graph.history[0].zoom, HISTORY as f32);
+    }
+    #[test]
+    fn bookmarks() {
+        let mut graph = Graph::new(Vec::new(), false, -1.0, 1.0);
+        graph.zoom = 3.0;
+        graph.offset = Vec3::new(1.0, 2.0, 0.0);
+        graph.save_bookmark("a");
+        graph.zoom = 5.0;
+        graph.save_bookmark("b");
+        graph.zoom = 1.0;
+        assert!(graph.load_bookmark("a"));
+        assert_eq!(graph.zoom, 3.0);
+        assert!(graph.offset == Vec3::new(1.0, 2.0, 0.0));
+        assert!(graph.undo());
+        assert_eq!(graph.zoom, 1.0);
+        graph.zoom = 7.0;
+        graph.save_bookmark("b");
+        assert!(graph.load_bookmark("b"));
+        assert_eq!(graph.zoom, 7.0);
+        assert_eq!(graph.get_bookmarks().collect::<Vec<_>>(), ["a", "b"]);
+        assert!(graph.remove_bookmark("a"));
+        assert!(!graph.remove_bookmark("a"));
+        assert!(!graph.load_bookmark("a"));
+    }
+    #[test]
+    fn discontinuities() {
+        let mut graph = Graph::new(Vec::new(), false, -1.0, 1.0);
+        graph.set_discontinuity(Some(Discontinuity::default()));
+        let step = |graph: &Graph, f: fn(f32) -> f32, x: f32, dx: f32| {
+            let (a, b, c) = (f(x - dx), f(x), f(x + dx));
+            graph.is_discontinuous((c - b).abs(), (b - a).abs(), 1.0, (b, c))
+        };
+        assert!(step(&graph, f32::tan, 1.5, 0.1));
+        assert!(step(&graph, |x| 1.0 / x, -0.05, 0.1));
+        assert!(!step(&graph, |x| (40.0 * x).sin(), -0.01, 0.02));
+        assert!(!step(&graph, |x| 20.0 * x, -0.05, 0.1));
+        assert!(step(
+            &graph,
+            |x| if x < 0.0 { 0.0 } else { 4.0 },
+            -0.05,
+            0.1
+        ));
+        assert!(!step(&graph, |x| x * x, 1.0, 0.1));
+        graph.set_discontinuity(None);
+        assert!(!step(&graph, f32::tan, 1.5, 0.1));
+    }
+    #[test]
+    fn noise_is_not_broken() {
+        let graph = Graph::new(Vec::new(), false, -1.0, 1.0);
+        let mut seed = 7u32;
+        let mut rand = || {
+            seed = seed.wrapping_mul(1664525).wrapping_add(1013904223);
+            (seed >> 8) as f32 / (1 << 24) as f32 - 0.5
+        };
+        for (scale, offset) in [(0.4, 0.0), (0.8, 0.0), (0.4, 0.5)] {
+            let ys: Vec<f32> = (0..1000).map(|_| scale * rand() + offset).collect();
+            let mut prev = f32::INFINITY;
+            for w in ys.windows(2) {
+                let jump = (w[1] - w[0]).abs();
+                assert!(!graph.is_discontinuous(jump, prev, 1.0, (w[0], w[1])));
+                prev = jump;
+            }
+        }
+    }
+    #[test]
+    fn decimate_columns() {
+        let mut graph = Graph::new(Vec::new(), false, -1.0, 1.0);
+        graph.screen = Vec2::new(100.0, 100.0);
+        graph.screen_offset = Vec2::new(50.0, 50.0);
+        let mut seed = 3u32;
+        let mut rand = || {
+            seed = seed.wrapping_mul(1664525).wrapping_add(1013904223);
+            (seed >> 8) as f32 / (1 << 24) as f32 - 0.5
+        };
+        let pts: Vec<(f32, Option<f32>)> = (0..20000)
+            .map(|i| {
+                let x = -3.0 + 6.0 * i as f32 / 20000.0;
+                let y = if (0.5..0.52).contains(&x) {
+                    None
+                } else {
+                    Some(rand())
+                };
+                (x, y)
+            })
+            .collect();
+        let out = graph.decimate(pts.iter().copied());
+        assert!(out.len() < 4 * 104);
+        let column = |x: f32| graph.column(x);
+        for n in -1..=101 {
+            let raw: Vec<f32> = pts
+                .iter()
+                .filter(|(x, _)| column(*x) == n)
+                .filter_map(|p| p.1)
+                .collect();
+            let kept: Vec<f32> = out
+                .iter()
+                .filter(|(x, _)| column(*x) == n)
+                .filter_map(|p| p.1)
+                .collect();
+            let max = |v: &[f32]| v.iter().copied().fold(f32::NEG_INFINITY, f32::max);
+            let min = |v: &[f32]| v.iter().copied().fold(f32::INFINITY, f32::min);
+            assert_eq!(max(&raw), max(&kept));
+            assert_eq!(min(&raw), min(&kept));
+        }
+        assert!(out.iter().filter(|(x, _)| column(*x) == -1).count() <= 4);
+        assert!(out.iter().filter(|(x, _)| column(*x) == 101).count() <= 4);
+        let gap = out.iter().position(|p| p.1.is_none()).unwrap();
+        assert!(out[gap - 1].0 < 0.5 && out[gap + 1].0 >= 0.52);
+        assert_eq!(out.iter().filter(|p| p.1.is_none()).count(), 1);
+    }
+    #[test]
+    fn legend_rows_fit_text() {
+        let data = vec![GraphType::Width(vec![], -1.0, 1.0); 12];
+        let mut graph = Graph::new(data, true, -1.0, 1.0);
+        graph.set_legend(true);
+        graph.screen = Vec2::new(400.0, 400.0);
+        graph.fonts.export_scale = 2.0;
+        let ctx = Context::default();
+        let mut rows = Vec::new();
+        let _ = ctx.run(Default::default(), |ctx| {
+            CentralPanel::default().show(ctx, |ui| rows = graph.legend_rows(ui.painter()));
+        });
+        assert_eq!(rows.len(), 24);
+        for w in rows.windows(2) {
+            assert!(w[0].0.max.y <= w[1].0.min.y);
+        }
+        for (rect, _, _, galley) in &rows {
+            assert!(rect.height() >= galley.size().y);
+            assert!(rect.width() >= galley.size().x + 16.0);
+            assert!(rect.max.x <= graph.screen.x);
+        }
+    }
+    #[test]
+    fn annotation_handles() {
+        let mut graph = Graph::new(Vec::new(), false, -1.0, 1.0);
+        let a = graph.add_annotation(Annotation::VLine(0.0), Color32::RED);
+        let b = graph.add_annotation(Annotation::HLine(1.0), Color32::RED);
+        let c = graph.add_annotation(Annotation::VLine(2.0), Color32::RED);
+        assert!(matches!(
+            graph.remove_annotation(a),
+            Some(Annotation::VLine(0.0))
+        ));
+        assert!(matches!(
+            graph.remove_annotation(c),
+            Some(Annotation::VLine(2.0))
+        ));
+        let revision = graph.revision;
+        assert!(graph.remove_annotation(a).is_none());
+        assert_eq!(graph.revision, revision);
+        assert!(matches!(
+            graph.remove_annotation(b),
+            Some(Annotation::HLine(1.0))
+        ));
+        assert!(graph.annotations.is_empty());
+        assert!(graph.add_annotation(Annotation::VLine(0.0), Color32::RED) != a);
+    }
+    #[test]
+    fn kd_tree_nearest() {
+        let mut seed = 1u32;
+        let mut rand = || {
+            seed = seed.wrapping_mul(1664525).wrapping_add(1013904223);
+            (seed >> 8) as f32 / (1 << 24) as f32 * 2.0 - 1.0
+        };
+        let points: Vec<_> = (0..500).map(|_| Pos2::new(rand(), rand())).collect();
+        let samples = points
+            .iter()
+            .enumerate()
+            .map(|(index, &pos)| Sample {
+                pos,
+                series: 0,
+                index,
+                x: pos.x,
+                value: Complex::Real(pos.y),
+                alt: false,
+            })
+            .collect();
+        let tree = KdTree::new(samples);
+        for _ in 0..200 {
+            let p = Pos2::new(rand() * 1.5, rand() * 1.5);
+            let best = points
+                .iter()
+                .map(|q| q.distance_sq(p))
+                .fold(f32::INFINITY, f32::min);
+            let found = tree.nearest(p, 4.0).unwrap();
+            assert_eq!(found.pos.distance_sq(p), best);
+            assert_eq!(points[found.index], found.pos);
+            let r = best.sqrt() * 0.99;
+            assert!(tree.nearest(p, r).is_none());
+        }
+        assert!(KdTree::new(Vec::new()).nearest(Pos2::ZERO, 1.0).is_none());
+    }
+    #[test]
+    fn integrate_matches_analytic() {
+        let square: Vec<_> = (0..=1000)
+            .map(|i| Complex::Complex((i as f32 / 1000.0).powi(2), 1.0))
+            .collect();
+        let line = vec![(2.0, Complex::Real(4.0)), (0.0, Complex::Real(0.0))];
+        let graph = Graph::new(
+            vec![
+                GraphType::Width(square, 0.0, 1.0),
+                GraphType::Coord(line),
+                GraphType::Function(Arc::new(|x: f32| Complex::Real(x.sin()))),
+                GraphType::Width(vec![Complex::Real(1.0)], 0.0, 1.0),
+            ],
+            false,
+            -1.0,
+            1.0,
+        );
+        let (re, im) = graph.integrate(0, 0.0, 1.0).unwrap().to_options();
+        assert!((re.unwrap() - 1.0 / 3.0).abs() < 1e-4);
+        assert!((im.unwrap() - 1.0).abs() < 1e-4);
+        let (re, _) = graph.integrate(0, 1.0, 0.5).unwrap().to_options();
+        assert!((re.unwrap() + 7.0 / 24.0).abs() < 1e-4);
+        let (re, _) = graph.integrate(1, 0.5, 1.5).unwrap().to_options();
+        assert!((re.unwrap() - 2.0).abs() < 1e-5);
+        let (re, _) = graph.integrate(2, 0.0, PI).unwrap().to_options();
+        assert!((re.unwrap() - 2.0).abs() < 1e-4);
+        assert!(graph.integrate(3, 0.0, 1.0).is_none());
+        assert!(graph.integrate(4, 0.0, 1.0).is_none());
+    }
+    #[test]
+    fn theme_keeps_series_colors() {
+        let data = vec![GraphType::Width(vec![], -1.0, 1.0); 8];
+        let mut graph = Graph::new(data, false, -1.0, 1.0);
+        assert_eq!(graph.main_colors.len(), 8);
+        for (i, a) in graph.main_colors.iter().enumerate() {
+            assert!(!graph.main_colors[..i].contains(a));
+        }
+        graph.set_theme(Theme::dark());
+        assert_eq!(graph.main_colors.len(), 8);
+        assert_eq!(graph.alt_colors.len(), 8);
+        assert!(Theme::dark().main_colors != Theme::light().main_colors);
+    }
+    #[test]
+    fn dimension_change_applies_mode() {
+        let mut graph = Graph::new(vec![GraphType::Width(vec![], -1.0, 1.0)], false, -1.0, 1.0);
+        assert!(!graph.is_3d);
+        let handle = graph.insert_series(surface());
+        assert!(graph.is_3d);
+        graph.remove_series(handle);
+        assert!(!graph.is_3d);
+    }
+    #[cfg(feature = "serde")]
+    #[test]
+    fn save_load_round_trip() {
+        let values = vec![
+            Complex::Real(1.0),
+            Complex::Real(f32::NAN),
+            Complex::Complex(f32::INFINITY, f32::NEG_INFINITY),
+            Complex::from(None, None),
+        ];
+        let mut graph = Graph::new(
+            vec![
+                GraphType::Function(Arc::new(Complex::Real)),
+                GraphType::Width(values, -1.0, 1.0),
+            ],
+            true,
+            -1.0,
+            1.0,
+        );
+        let handle = graph.get_handles().nth(1).unwrap();
+        graph.set_part_visible(handle, Show::Imag, false);
+        graph.set_measure_series(Some(handle));
+        graph.set_resolution(32);
+        graph.set_crosshair(true);
+        let (saved, skipped) = graph.save(true).unwrap();
+        assert_eq!(skipped, 1);
+        let mut loaded = Graph::new(Vec::new(), false, 0.0, 1.0);
+        loaded.load(&saved).unwrap();
+        assert_eq!(loaded.data.len(), 1);
+        let GraphType::Width(values, start, end) = &loaded.data[0] else {
+            panic!()
+        };
+        assert_eq!((*start, *end), (-1.0, 1.0));
+        let values: Vec<_> = values.iter().map(|v| v.to_options()).collect();
+        assert_eq!(values[0], (Some(1.0), None));
+        assert!(values[1].0.unwrap().is_nan());
+        assert_eq!(values[2], (Some(f32::INFINITY), Some(f32::NEG_INFINITY)));
+        assert!(values[3].0.unwrap().is_nan() && values[3].1.unwrap().is_nan());
+        assert!(loaded.series[0].real && !loaded.series[0].imag);
+        assert_eq!(loaded.resolution, 32);
+        assert!(loaded.crosshair);
+        assert_eq!(loaded.measure_series, loaded.get_handles().next());
+    }
+    #[test]
+    fn load_checks_limits() {
+        let data = vec![GraphType::Width(
+            (0..8).map(|i| Complex::Real(i as f32)).collect(),
+            0.0,
+            1.0,
+        )];
+        let mut graph = Graph::new(data, false, -1.0, 1.0);
+        let mut config = graph.get_config(false);
+        config.resolution = 0;
+        config.fov = 0.0;
+        config.view_distance = 0.0;
+        config.capacity = Some(4);
+        graph.set_config(config);
+        assert_eq!(graph.resolution, 2);
+        assert!(graph.fov > 0.0);
+        assert!(graph.view_distance > 1.0);
+        let GraphType::Width(data, _, _) = &graph.data[0] else {
+            panic!()
+        };
+        assert_eq!(data.len(), 4);
+    }
+    #[test]
+    fn perspective_near_plane() {
+        let mut graph = Graph::new(Vec::new(), false, -1.0, 1.0);
+        graph.set_perspective(true);
+        let cam = graph.camera();
+        for d in [0.0, cam * 0.5, cam * (1.0 - NEAR), cam, cam * 2.0] {
+            let scale = graph.perspective_scale(d);
+            assert!(scale.is_finite() && scale > 0.0);
+        }
+        assert!(graph.perspective_scale(0.0) < graph.perspective_scale(cam * 0.5));
+        assert_eq!(
+            graph.perspective_scale(cam),
+            graph.perspective_scale(cam * 2.0)
+        );
+        let depth = |d: f32| d / (2.0 * 3.0f32.sqrt()) + 0.5;
+        assert!(!graph.culled(depth(cam * 0.5)));
+        assert!(graph.culled(depth(cam)));
+        assert!(graph.culled(depth(cam * 2.0)));
+        graph.set_perspective(false);
+        assert!(!graph.culled(depth(cam * 2.0)));
+    }
+    #[test]
+    fn append_with_capacity() {
+        let values = (0..5).map(|i| Complex::Real(i as f32)).collect();
+        let mut graph = Graph::new(vec![GraphType::Width(values, 0.0, 4.0)], false, 0.0, 4.0);
+        graph.set_capacity(Some(4));
+        let GraphType::Width(data, start, end) = &graph.data[0] else {
+            panic!()
+        };
+        assert_eq!((data.len(), *start, *end), (4, 1.0, 4.0));
+        let handle = graph.get_handles().next().unwrap();
+        graph.set_capacity(Some(16));
+        for i in 5..40 {
+            assert!(graph.append_series_values(handle, &[Complex::Real(i as f32)]));
+            let GraphType::Width(data, start, end) = &graph.data[0] else {
+                panic!()
+            };
+            assert!(data.len() <= 16);
+            assert_eq!(*end, i as f32);
+            assert_eq!(*start, *end - (data.len() - 1) as f32);
+            assert_eq!(data[0].to_options().0, Some(*start));
+        }
+        assert_eq!(graph.newest, Some(39.0));
+        assert!(!graph.append_series_points(handle, &[(0.0, Complex::Real(0.0))]));
+    }
 }